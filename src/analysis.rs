@@ -0,0 +1,244 @@
+use crate::compile::ForestCompileError;
+use crate::forest_runtime::{bundled_library, resolve_include};
+use crate::lexer::{Position, Token, TokenKind, lex};
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+
+/// How a name was defined.
+#[derive(Clone, Copy, PartialEq)]
+pub enum DefinitionKind {
+    /// `: name ... ;`
+    Word,
+    /// `:: name ... ;`
+    ConstantWord,
+    /// `-> name`
+    Variable,
+    /// `=> name`
+    Constant,
+}
+
+pub struct Definition {
+    pub name: String,
+    pub kind: DefinitionKind,
+    /// The module the definition is in, if any.
+    pub module: Option<String>,
+    /// Where the name is written in the definition.
+    pub position: Position,
+    /// The stack-effect comment right after a word's name, like `( a b -- c )`.
+    pub effect: Option<String>,
+    /// The tokens of a word's body, as written.
+    pub body: Vec<String>,
+}
+
+impl Definition {
+    /// Whether `raw`, a word in the source, refers to this definition.
+    pub fn is_named(&self, raw: &str) -> bool {
+        match (raw.rsplit_once('.'), &self.module) {
+            (Some((module, name)), Some(m)) if !module.is_empty() && !name.is_empty() => {
+                module == m && name == self.name
+            }
+            _ => raw == self.name,
+        }
+    }
+
+    /// The definition as it would be written, for showing to the user.
+    pub fn signature(&self) -> String {
+        let mut parts: Vec<&str> = vec![match self.kind {
+            DefinitionKind::Word => ":",
+            DefinitionKind::ConstantWord => "::",
+            DefinitionKind::Variable => "->",
+            DefinitionKind::Constant => "=>",
+        }];
+        parts.push(&self.name);
+        if let Some(effect) = &self.effect {
+            parts.push(effect);
+        }
+        parts.extend(self.body.iter().map(String::as_str));
+        if matches!(
+            self.kind,
+            DefinitionKind::Word | DefinitionKind::ConstantWord
+        ) {
+            parts.push(";");
+        }
+        parts.join(" ")
+    }
+}
+
+/// What tools like the language server know about a source file without
+/// running it.
+pub struct Analysis {
+    pub tokens: Vec<Token>,
+    pub definitions: Vec<Definition>,
+    /// The files included with a string literal right before `include`.
+    pub includes: Vec<String>,
+}
+
+impl Analysis {
+    /// Finds the token covering a position.
+    pub fn token_at(&self, position: Position) -> Option<&Token> {
+        self.tokens.iter().find(|t| {
+            t.position.line == position.line
+                && t.position.column <= position.column
+                && position.column < t.position.column + t.raw.chars().count()
+        })
+    }
+
+    /// Finds the first definition a word in the source refers to.
+    pub fn definition(&self, raw: &str) -> Option<&Definition> {
+        self.definitions.iter().find(|d| d.is_named(raw))
+    }
+
+    /// Finds every word in the source that refers to a definition, including
+    /// the names in definitions.
+    pub fn references<'a>(&'a self, definition: &'a Definition) -> impl Iterator<Item = &'a Token> {
+        self.tokens
+            .iter()
+            .filter(|t| matches!(t.kind, TokenKind::Word(_)) && definition.is_named(&t.raw))
+    }
+}
+
+/// Reads the definitions and includes out of a source file. Fails only if
+/// the source can't be split into tokens.
+pub fn analyze(source: &str) -> Result<Analysis, ForestCompileError> {
+    let tokens = lex(source)?;
+    let mut definitions = Vec::new();
+    let mut includes = Vec::new();
+    // The blocks closed by `end`, with the names of the modules among them.
+    let mut blocks: Vec<Option<String>> = Vec::new();
+    let code: Vec<&Token> = tokens
+        .iter()
+        .filter(|t| !matches!(t.kind, TokenKind::Comment(_)))
+        .collect();
+    for (i, token) in code.iter().enumerate() {
+        let next = code.get(i + 1);
+        match token.raw.as_str() {
+            ":" | "::" | "->" | "=>" => {
+                let Some(name) = next else { continue };
+                let kind = match token.raw.as_str() {
+                    ":" => DefinitionKind::Word,
+                    "::" => DefinitionKind::ConstantWord,
+                    "->" => DefinitionKind::Variable,
+                    _ => DefinitionKind::Constant,
+                };
+                let (effect, body) = match kind {
+                    DefinitionKind::Word | DefinitionKind::ConstantWord => {
+                        word_body(&tokens, name.position)
+                    }
+                    _ => (None, vec![]),
+                };
+                definitions.push(Definition {
+                    name: name.raw.clone(),
+                    kind,
+                    module: blocks.iter().rev().find_map(|b| b.clone()),
+                    position: name.position,
+                    effect,
+                    body,
+                });
+            }
+            "module" => blocks.push(next.map(|n| n.raw.clone())),
+            "try" | "for" => blocks.push(None),
+            "end" => {
+                blocks.pop();
+            }
+            "include" if i > 0 => {
+                if let TokenKind::Str(path) = &code[i - 1].kind {
+                    includes.push(path.clone());
+                }
+            }
+            _ => {}
+        }
+    }
+    Ok(Analysis {
+        tokens,
+        definitions,
+        includes,
+    })
+}
+
+/// Reads the stack-effect comment and body of the word whose name is at
+/// `name`, up to its `;`.
+fn word_body(tokens: &[Token], name: Position) -> (Option<String>, Vec<String>) {
+    let start = match tokens.iter().position(|t| t.position == name) {
+        Some(i) => i + 1,
+        None => return (None, vec![]),
+    };
+    let mut rest = tokens[start..].iter().peekable();
+    let effect = rest
+        .next_if(|t| matches!(t.kind, TokenKind::Comment(_)) && t.raw.starts_with('('))
+        .map(|t| t.raw.clone());
+    let mut body = Vec::new();
+    let mut depth = 1;
+    for token in rest {
+        if matches!(token.kind, TokenKind::Word(_)) {
+            match token.raw.as_str() {
+                ":" | "::" => depth += 1,
+                ";" => depth -= 1,
+                _ => {}
+            }
+        }
+        if depth == 0 {
+            break;
+        }
+        body.push(token.raw.clone());
+    }
+    (effect, body)
+}
+
+/// Where a source file comes from. Bundled libraries have no file to point at.
+pub enum Source {
+    File(PathBuf),
+    Bundled,
+}
+
+pub struct SourceFile {
+    pub source: Source,
+    pub text: String,
+    pub analysis: Analysis,
+}
+
+/// Gathers a file and every file it includes, directly or not, with the file
+/// itself first and the prelude last. Files are read with `read`, so callers
+/// can use versions of them that aren't saved yet.
+pub fn visible(path: &Path, read: impl Fn(&Path) -> Option<String>) -> Vec<SourceFile> {
+    let open = |source: Source, text: String| {
+        analyze(&text).ok().map(|analysis| SourceFile {
+            source,
+            text,
+            analysis,
+        })
+    };
+    let mut found: Vec<SourceFile> = Vec::new();
+    let mut seen: HashSet<String> = HashSet::from([path.display().to_string()]);
+    let mut queue: Vec<SourceFile> = read(path)
+        .and_then(|text| open(Source::File(path.to_path_buf()), text))
+        .into_iter()
+        .collect();
+    while let Some(file) = queue.pop() {
+        for include in &file.analysis.includes {
+            let from = match &file.source {
+                Source::File(p) => Some(p.as_path()),
+                Source::Bundled => None,
+            };
+            if let Some(library) = bundled_library(include) {
+                if seen.insert(format!("<{include}>"))
+                    && let Some(f) = open(Source::Bundled, library.to_string())
+                {
+                    queue.push(f);
+                }
+            } else if let Some(p) = resolve_include(from, include)
+                && seen.insert(p.display().to_string())
+                && let Some(f) = read(&p).and_then(|text| open(Source::File(p), text))
+            {
+                queue.push(f);
+            }
+        }
+        found.push(file);
+    }
+    if !seen.contains("<std>")
+        && let Some(f) =
+            bundled_library("std").and_then(|std| open(Source::Bundled, std.to_string()))
+    {
+        found.push(f);
+    }
+    found
+}
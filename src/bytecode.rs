@@ -0,0 +1,690 @@
+use crate::compile::compile_mapped;
+use crate::forest_runtime::{ForestInstruction as fi, ForestValue, TablePair};
+use crate::lexer::Position;
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// The extension of bytecode files, which `forest run` loads instead of
+/// compiling.
+pub const EXTENSION: &str = "frc";
+
+/// The first bytes of every bytecode file.
+const MAGIC: &[u8; 4] = b"FRC\0";
+
+/// The version of the format. It has to change whenever the encoding does,
+/// including when instructions are added, since files built by another
+/// version can't be read.
+const VERSION: u32 = 3;
+
+// Opcodes of the instructions with operands. The others are numbered by
+// `unit_instructions!`.
+const PUSH: u8 = 100;
+const LOOP_INDEX: u8 = 101;
+const MAKE_WORD: u8 = 102;
+const MAKE_WORD_VAR: u8 = 103;
+const INVOKE_WORD: u8 = 104;
+const SET: u8 = 105;
+const SET_VAR: u8 = 106;
+const MODULE: u8 = 107;
+const ENTER_SCOPE: u8 = 108;
+const ENTER_GLOBAL_SCOPE: u8 = 109;
+const USING: u8 = 110;
+const ALIAS: u8 = 111;
+const SHADOW: u8 = 112;
+
+const NIL: u8 = 0;
+const INT: u8 = 1;
+const STRING: u8 = 2;
+const TABLE: u8 = 3;
+
+/// A compiled program, with the position in the source each instruction was
+/// compiled from.
+pub struct Bytecode {
+    /// The file the program was compiled from, which its includes are found
+    /// next to when it runs.
+    pub source: String,
+    pub program: Vec<fi>,
+    pub positions: Vec<Position>,
+}
+
+pub enum BytecodeError {
+    NotBytecode,
+    UnsupportedVersion(u32),
+    Corrupted(String),
+}
+
+impl fmt::Display for BytecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::NotBytecode => write!(f, "Not a Forest bytecode file!"),
+            Self::UnsupportedVersion(v) => write!(
+                f,
+                "Built for version {v} of the bytecode format, but this forest reads version \
+                 {VERSION}; build it again with `forest build`!"
+            ),
+            Self::Corrupted(reason) => write!(f, "The bytecode file is corrupted: {reason}!"),
+        }
+    }
+}
+
+/// FNV-1a, to notice files that were damaged after they were built.
+fn checksum(bytes: &[u8]) -> u32 {
+    bytes.iter().fold(0x811c9dc5, |hash, b| {
+        (hash ^ u32::from(*b)).wrapping_mul(0x01000193)
+    })
+}
+
+/// Numbers the instructions without operands from 0, in the order they are
+/// listed, for both encoding and decoding them.
+macro_rules! unit_instructions {
+    ($($name:ident),* $(,)?) => {
+        enum UnitOpcode {
+            $($name),*
+        }
+
+        fn unit_opcode(instr: &fi) -> Option<u8> {
+            match instr {
+                $(fi::$name => Some(UnitOpcode::$name as u8),)*
+                // Listed rather than matched with `_`, so that an instruction
+                // that is in neither list doesn't compile.
+                fi::Push(_)
+                | fi::LoopIndex(_)
+                | fi::MakeWord(_)
+                | fi::MakeWordVar(_)
+                | fi::InvokeWord(_)
+                | fi::Set(_)
+                | fi::SetVar(_)
+                | fi::Shadow(_)
+                | fi::Module(_)
+                | fi::EnterScope(_)
+                | fi::Using(_)
+                | fi::Alias(_, _) => None,
+            }
+        }
+
+        const UNIT_INSTRUCTIONS: &[fi] = &[$(fi::$name),*];
+    };
+}
+
+unit_instructions!(
+    Duplicate,
+    Drop,
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+    Stringify,
+    Concatenate,
+    Print,
+    Get,
+    Associate,
+    Keys,
+    Values,
+    If,
+    IfEnd,
+    Else,
+    And,
+    Or,
+    Not,
+    Eq,
+    GreaterThan,
+    LessThan,
+    Loop,
+    LoopEnd,
+    Break,
+    LoopStep,
+    ForEachNext,
+    EndWord,
+    Swap,
+    Rotate,
+    RotateBack,
+    Over,
+    Nip,
+    Tuck,
+    Pick,
+    Roll,
+    TwoDup,
+    TwoDrop,
+    TwoSwap,
+    DupIfTrue,
+    Depth,
+    ToReturn,
+    FromReturn,
+    FetchReturn,
+    Splat,
+    Include,
+    EndInclude,
+    Eval,
+    NextToken,
+    Emit,
+    EmitToken,
+    ExitScope,
+    Private,
+    Public,
+    Assert,
+    AssertEq,
+    AssertStack,
+    ExpectError,
+    ReadFile,
+    ReadLines,
+    WriteFile,
+    AppendFile,
+    FileExists,
+    DeleteFile,
+    RenameFile,
+    ListDir,
+    MakeDir,
+    Args,
+    GetEnv,
+    SetEnv,
+    Cwd,
+    ScriptPath,
+    Try,
+    Catch,
+    EndTry,
+    Throw,
+    Input,
+    Exit,
+    ExitWith,
+    Unshadow,
+);
+
+fn opcode(instr: &fi) -> u8 {
+    match instr {
+        fi::Push(_) => PUSH,
+        fi::LoopIndex(_) => LOOP_INDEX,
+        fi::MakeWord(_) => MAKE_WORD,
+        fi::MakeWordVar(_) => MAKE_WORD_VAR,
+        fi::InvokeWord(_) => INVOKE_WORD,
+        fi::Set(_) => SET,
+        fi::SetVar(_) => SET_VAR,
+        fi::Shadow(_) => SHADOW,
+        fi::Module(_) => MODULE,
+        fi::EnterScope(Some(_)) => ENTER_SCOPE,
+        fi::EnterScope(None) => ENTER_GLOBAL_SCOPE,
+        fi::Using(_) => USING,
+        fi::Alias(_, _) => ALIAS,
+        _ => unit_opcode(instr).unwrap_or_else(|| panic!("`{instr}` has no opcode")),
+    }
+}
+
+fn put_u32(out: &mut Vec<u8>, n: usize) {
+    let n = u32::try_from(n).expect("bytecode only holds up to u32::MAX of anything");
+    out.extend(n.to_le_bytes());
+}
+
+/// Collects the strings and constants of a program while encoding its
+/// instructions, so each is only stored once.
+#[derive(Default)]
+struct Encoder {
+    strings: Vec<u8>,
+    string_ids: HashMap<String, usize>,
+    constants: Vec<u8>,
+    constant_ids: HashMap<Vec<u8>, usize>,
+    code: Vec<u8>,
+}
+
+impl Encoder {
+    fn string(&mut self, s: &str) -> usize {
+        if let Some(id) = self.string_ids.get(s) {
+            return *id;
+        }
+        let id = self.string_ids.len();
+        put_u32(&mut self.strings, s.len());
+        self.strings.extend(s.as_bytes());
+        self.string_ids.insert(s.to_string(), id);
+        id
+    }
+
+    fn value(&mut self, value: &ForestValue, out: &mut Vec<u8>) {
+        match value {
+            ForestValue::Nil => out.push(NIL),
+            ForestValue::Int(i) => {
+                out.push(INT);
+                out.extend(i.to_le_bytes());
+            }
+            ForestValue::String(s) => {
+                out.push(STRING);
+                let id = self.string(s);
+                put_u32(out, id);
+            }
+            ForestValue::Table(pairs) => {
+                out.push(TABLE);
+                put_u32(out, pairs.len());
+                for pair in pairs {
+                    self.value(&pair.key, out);
+                    self.value(&pair.value, out);
+                }
+            }
+        }
+    }
+
+    fn constant(&mut self, value: &ForestValue) -> usize {
+        let mut encoded = Vec::new();
+        self.value(value, &mut encoded);
+        if let Some(id) = self.constant_ids.get(&encoded) {
+            return *id;
+        }
+        let id = self.constant_ids.len();
+        self.constants.extend(&encoded);
+        self.constant_ids.insert(encoded, id);
+        id
+    }
+
+    fn instruction(&mut self, instr: &fi) {
+        self.code.push(opcode(instr));
+        let operands: Vec<usize> = match instr {
+            fi::Push(v) => vec![self.constant(v)],
+            fi::LoopIndex(n) => vec![*n],
+            fi::MakeWord(s)
+            | fi::MakeWordVar(s)
+            | fi::InvokeWord(s)
+            | fi::Set(s)
+            | fi::SetVar(s)
+            | fi::Shadow(s)
+            | fi::Module(s)
+            | fi::EnterScope(Some(s))
+            | fi::Using(s) => vec![self.string(s)],
+            fi::Alias(a, m) => vec![self.string(a), self.string(m)],
+            _ => vec![],
+        };
+        operands
+            .into_iter()
+            .for_each(|n| put_u32(&mut self.code, n));
+    }
+}
+
+/// Encodes a program. Its layout is the magic bytes and the version, then
+/// the strings, the constants, the source's name, the instructions and their
+/// positions, each preceded by how many there are, then a checksum of all of
+/// it. Numbers are little-endian.
+pub fn encode(bytecode: &Bytecode) -> Vec<u8> {
+    let mut encoder = Encoder::default();
+    let source = encoder.string(&bytecode.source);
+    bytecode
+        .program
+        .iter()
+        .for_each(|instr| encoder.instruction(instr));
+    let mut out = Vec::new();
+    out.extend(MAGIC);
+    out.extend(VERSION.to_le_bytes());
+    put_u32(&mut out, encoder.string_ids.len());
+    out.extend(&encoder.strings);
+    put_u32(&mut out, encoder.constant_ids.len());
+    out.extend(&encoder.constants);
+    put_u32(&mut out, source);
+    put_u32(&mut out, bytecode.program.len());
+    out.extend(&encoder.code);
+    put_u32(&mut out, bytecode.positions.len());
+    for position in &bytecode.positions {
+        put_u32(&mut out, position.line);
+        put_u32(&mut out, position.column);
+    }
+    out.extend(checksum(&out).to_le_bytes());
+    out
+}
+
+fn corrupted(reason: &str) -> BytecodeError {
+    BytecodeError::Corrupted(reason.to_string())
+}
+
+/// Reads the parts of a file in order, failing if they aren't there.
+struct Decoder<'a> {
+    bytes: &'a [u8],
+    strings: Vec<String>,
+    constants: Vec<ForestValue>,
+}
+
+impl Decoder<'_> {
+    fn take(&mut self, n: usize) -> Result<&[u8], BytecodeError> {
+        if self.bytes.len() < n {
+            return Err(corrupted("it ends early"));
+        }
+        let (taken, rest) = self.bytes.split_at(n);
+        self.bytes = rest;
+        Ok(taken)
+    }
+
+    fn u8(&mut self) -> Result<u8, BytecodeError> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u32(&mut self) -> Result<usize, BytecodeError> {
+        let bytes = self.take(4)?.try_into().expect("four bytes were taken");
+        Ok(u32::from_le_bytes(bytes) as usize)
+    }
+
+    /// Reads how many of something follow, which can't be more than the
+    /// bytes left, so a damaged count doesn't allocate too much.
+    fn count(&mut self) -> Result<usize, BytecodeError> {
+        match self.u32()? {
+            n if n > self.bytes.len() => Err(corrupted("a count is larger than the file")),
+            n => Ok(n),
+        }
+    }
+
+    fn string(&mut self) -> Result<String, BytecodeError> {
+        let id = self.u32()?;
+        match self.strings.get(id) {
+            Some(s) => Ok(s.clone()),
+            None => Err(corrupted(&format!("string {id} does not exist"))),
+        }
+    }
+
+    fn value(&mut self) -> Result<ForestValue, BytecodeError> {
+        match self.u8()? {
+            NIL => Ok(ForestValue::Nil),
+            INT => {
+                let bytes = self.take(8)?.try_into().expect("eight bytes were taken");
+                Ok(ForestValue::Int(i64::from_le_bytes(bytes)))
+            }
+            STRING => Ok(ForestValue::String(self.string()?)),
+            TABLE => {
+                let mut pairs = Vec::new();
+                for _ in 0..self.count()? {
+                    let key = self.value()?;
+                    let value = self.value()?;
+                    pairs.push(TablePair { key, value });
+                }
+                Ok(ForestValue::Table(pairs))
+            }
+            tag => Err(corrupted(&format!("unknown kind of value {tag}"))),
+        }
+    }
+
+    fn instruction(&mut self) -> Result<fi, BytecodeError> {
+        Ok(match self.u8()? {
+            PUSH => {
+                let id = self.u32()?;
+                match self.constants.get(id) {
+                    Some(v) => fi::Push(v.clone()),
+                    None => return Err(corrupted(&format!("constant {id} does not exist"))),
+                }
+            }
+            LOOP_INDEX => fi::LoopIndex(self.u32()?),
+            MAKE_WORD => fi::MakeWord(self.string()?),
+            MAKE_WORD_VAR => fi::MakeWordVar(self.string()?),
+            INVOKE_WORD => fi::InvokeWord(self.string()?),
+            SET => fi::Set(self.string()?),
+            SET_VAR => fi::SetVar(self.string()?),
+            SHADOW => fi::Shadow(self.string()?),
+            MODULE => fi::Module(self.string()?),
+            ENTER_SCOPE => fi::EnterScope(Some(self.string()?)),
+            ENTER_GLOBAL_SCOPE => fi::EnterScope(None),
+            USING => fi::Using(self.string()?),
+            ALIAS => fi::Alias(self.string()?, self.string()?),
+            op => match UNIT_INSTRUCTIONS.get(op as usize) {
+                Some(instr) => instr.clone(),
+                None => return Err(corrupted(&format!("unknown instruction {op}"))),
+            },
+        })
+    }
+}
+
+/// Decodes a program encoded by `encode`, checking that it was built for
+/// this version of the format and hasn't been damaged since.
+pub fn decode(bytes: &[u8]) -> Result<Bytecode, BytecodeError> {
+    let Some(rest) = bytes.strip_prefix(MAGIC) else {
+        return Err(BytecodeError::NotBytecode);
+    };
+    // The version is checked first, since other versions may not even have a
+    // checksum where this one does.
+    let Some(version) = rest.get(..4) else {
+        return Err(corrupted("it ends early"));
+    };
+    let version = u32::from_le_bytes(version.try_into().expect("four bytes were taken"));
+    if version != VERSION {
+        return Err(BytecodeError::UnsupportedVersion(version));
+    }
+    let Some((body, sum)) = bytes.split_at_checked(bytes.len().saturating_sub(4)) else {
+        return Err(corrupted("it ends early"));
+    };
+    if body.len() < MAGIC.len() + 4 || checksum(body).to_le_bytes() != sum {
+        return Err(corrupted("its checksum does not match its contents"));
+    }
+    let mut decoder = Decoder {
+        bytes: &body[MAGIC.len() + 4..],
+        strings: Vec::new(),
+        constants: Vec::new(),
+    };
+    for _ in 0..decoder.count()? {
+        let len = decoder.u32()?;
+        match String::from_utf8(decoder.take(len)?.to_vec()) {
+            Ok(s) => decoder.strings.push(s),
+            Err(_) => return Err(corrupted("a string is not valid UTF-8")),
+        }
+    }
+    for _ in 0..decoder.count()? {
+        let value = decoder.value()?;
+        decoder.constants.push(value);
+    }
+    let source = decoder.string()?;
+    let mut program = Vec::new();
+    for _ in 0..decoder.count()? {
+        program.push(decoder.instruction()?);
+    }
+    let mut positions = Vec::new();
+    for _ in 0..decoder.count()? {
+        let line = decoder.u32()?;
+        let column = decoder.u32()?;
+        positions.push(Position { line, column });
+    }
+    if positions.len() != program.len() {
+        return Err(corrupted("its source map does not match its instructions"));
+    }
+    if !decoder.bytes.is_empty() {
+        return Err(corrupted("it has more after its source map"));
+    }
+    Ok(Bytecode {
+        source,
+        program,
+        positions,
+    })
+}
+
+/// Compiles a file to bytecode, written next to it with the `.frc` extension
+/// unless `-o <path>` says otherwise.
+pub fn run_build(args: &[String]) -> Result<(), ()> {
+    let mut input: Option<&String> = None;
+    let mut output: Option<PathBuf> = None;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match (arg.as_str(), input) {
+            ("-o", _) => match args.next() {
+                Some(path) => output = Some(PathBuf::from(path)),
+                None => {
+                    eprintln!("`-o` must be followed by the path to write to");
+                    return Err(());
+                }
+            },
+            (_, None) => input = Some(arg),
+            (_, Some(_)) => {
+                eprintln!("Unknown option {arg}");
+                return Err(());
+            }
+        }
+    }
+    let Some(input) = input else {
+        eprintln!("Please provide a file name!");
+        return Err(());
+    };
+    let output = output.unwrap_or_else(|| Path::new(input).with_extension(EXTENSION));
+    let source = match fs::read_to_string(input) {
+        Ok(s) => s,
+        Err(e) => {
+            eprintln!("Could not read {input}, reason: {e}");
+            return Err(());
+        }
+    };
+    let (program, positions) = match compile_mapped(&source, Some(Path::new(input))) {
+        Ok(c) => c,
+        Err(e) => {
+            eprintln!("Error in compilation: {e}");
+            return Err(());
+        }
+    };
+    // Where the program is run from can't be known, so the path is kept whole.
+    let source = fs::canonicalize(input).map_or(input.clone(), |p| p.display().to_string());
+    let bytecode = Bytecode {
+        source,
+        program,
+        positions,
+    };
+    if let Err(e) = fs::write(&output, encode(&bytecode)) {
+        eprintln!("Could not write {}, reason: {e}", output.display());
+        return Err(());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::forest_runtime::{ForestRuntime, ForestStatus};
+
+    /// Every instruction, with each kind of operand.
+    fn every_instruction() -> Vec<fi> {
+        let string = |s: &str| ForestValue::String(s.to_string());
+        let mut program = vec![
+            fi::Push(ForestValue::Nil),
+            fi::Push(ForestValue::Int(-7)),
+            fi::Push(string("text")),
+            fi::Push(ForestValue::Table(vec![TablePair {
+                key: string("key"),
+                value: ForestValue::Table(vec![]),
+            }])),
+            fi::LoopIndex(1),
+            fi::MakeWord("word".to_string()),
+            fi::MakeWordVar("word".to_string()),
+            fi::InvokeWord("word".to_string()),
+            fi::Set("constant".to_string()),
+            fi::SetVar("variable".to_string()),
+            fi::Shadow("variable".to_string()),
+            fi::Module("module".to_string()),
+            fi::EnterScope(Some("module".to_string())),
+            fi::EnterScope(None),
+            fi::Using("module".to_string()),
+            fi::Alias("alias".to_string(), "module".to_string()),
+        ];
+        program.extend_from_slice(UNIT_INSTRUCTIONS);
+        program
+    }
+
+    fn build() -> Vec<u8> {
+        let program = every_instruction();
+        let positions = (1..=program.len())
+            .map(|line| Position { line, column: 2 })
+            .collect();
+        encode(&Bytecode {
+            source: "main.frt".to_string(),
+            program,
+            positions,
+        })
+    }
+
+    #[test]
+    fn every_instruction_round_trips() {
+        let decoded = match decode(&build()) {
+            Ok(b) => b,
+            Err(e) => panic!("{e}"),
+        };
+        assert_eq!(decoded.source, "main.frt");
+        let shown = |program: &[fi]| program.iter().map(|i| i.to_string()).collect::<Vec<_>>();
+        assert_eq!(shown(&decoded.program), shown(&every_instruction()));
+        assert!(
+            decoded
+                .positions
+                .iter()
+                .enumerate()
+                .all(|(i, p)| p.line == i + 1 && p.column == 2)
+        );
+    }
+
+    #[test]
+    fn errors_are_located_in_the_source() {
+        let (program, positions) = match compile_mapped("1 \"a\" +", None) {
+            Ok(c) => c,
+            Err(e) => panic!("{e}"),
+        };
+        let bytes = encode(&Bytecode {
+            source: "main.frt".to_string(),
+            program,
+            positions,
+        });
+        let Ok(decoded) = decode(&bytes) else {
+            panic!("the program doesn't decode");
+        };
+        let mut runtime = ForestRuntime::new(&decoded.program);
+        runtime.set_source_map(&decoded.source, &decoded.positions);
+        assert!(matches!(runtime.run_steps(10), ForestStatus::Errored(_)));
+        assert_eq!(runtime.location().as_deref(), Some("main.frt:1:7"));
+    }
+
+    #[test]
+    fn includes_are_found_next_to_the_source() {
+        let dir = std::env::temp_dir().join(format!("forest-build-{}", std::process::id()));
+        let write = |path: &Path, contents: &str| {
+            fs::create_dir_all(path.parent().unwrap_or(&dir)).unwrap_or_else(|e| panic!("{e}"));
+            fs::write(path, contents).unwrap_or_else(|e| panic!("{e}"));
+        };
+        write(&dir.join("src/lib.frt"), ": helper ( -- n ) 42 ;");
+        write(&dir.join("src/main.frt"), "\"lib.frt\" include helper");
+        // Built somewhere else than the source, with nothing to include there.
+        let output = dir.join("out/main.frc");
+        write(&output, "");
+        let args = [dir.join("src/main.frt"), "-o".into(), output.clone()]
+            .map(|p| p.display().to_string());
+        let run = || {
+            run_build(&args).ok()?;
+            let bytecode = decode(&fs::read(&output).ok()?).ok()?;
+            let mut runtime = ForestRuntime::new(&bytecode.program);
+            runtime.set_source_path(Path::new(&bytecode.source));
+            runtime.run_steps(20);
+            runtime.stack().last().cloned()
+        };
+        let top = run();
+        fs::remove_dir_all(&dir).ok();
+        assert!(matches!(top, Some(ForestValue::Int(42))));
+    }
+
+    #[test]
+    fn opcodes_stay_the_same() {
+        // Renumbering the instructions needs a new version of the format.
+        assert_eq!(opcode(&fi::Duplicate), 0);
+        assert_eq!(opcode(&fi::Exit), 77);
+        assert_eq!(opcode(&fi::Alias(String::new(), String::new())), ALIAS);
+    }
+
+    #[test]
+    fn rejects_files_that_are_not_bytecode() {
+        assert!(matches!(
+            decode(b"1 2 + .\n"),
+            Err(BytecodeError::NotBytecode)
+        ));
+        let mut bytes = build();
+        bytes[4..8].copy_from_slice(&(VERSION + 1).to_le_bytes());
+        assert!(matches!(
+            decode(&bytes),
+            Err(BytecodeError::UnsupportedVersion(v)) if v == VERSION + 1
+        ));
+    }
+
+    #[test]
+    fn rejects_damaged_files() {
+        let bytes = build();
+        for i in MAGIC.len() + 4..bytes.len() {
+            let mut damaged = bytes.clone();
+            damaged[i] ^= 0x20;
+            assert!(
+                matches!(decode(&damaged), Err(BytecodeError::Corrupted(_))),
+                "changing byte {i} went unnoticed"
+            );
+        }
+        assert!(matches!(
+            decode(&bytes[..bytes.len() - 1]),
+            Err(BytecodeError::Corrupted(_))
+        ));
+    }
+}
@@ -1,42 +1,313 @@
-use crate::forest_runtime::{ForestInstruction as fi, ForestValue};
+use crate::forest_runtime::{
+    Emitted, ForestInstruction as fi, ForestRuntime, ForestStatus, ForestValue, bundled_library,
+    resolve_include,
+};
+use crate::lexer::{Position, Token, TokenKind, lex};
+use std::collections::{HashSet, VecDeque};
 use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::vec;
 
 pub enum ForestCompileError {
     OutOfBoundsWord,
     // this means a word that doesn't end by the time the program ends compilation.
+    MissingName(String),
+    MalformedImport,
+    MalformedFor,
+    Unbalanced(String, Position),
+    MismatchedBlock(String, String, Position),
+    UnterminatedBlock(String, String, Position),
+    CatchOutsideTry,
+    TryWithoutCatch,
+    UnterminatedString(Position),
+    UnknownEscape(char, Position),
+    InvalidEscape(String, Position),
+    UnterminatedComment(Position),
+    NumberOutOfRange(String, Position),
+    MisplacedUnderscore(String, Position),
+    MisplacedImmediate,
+    ImmediateWordFailed(String, String, Position),
 }
 
-impl fmt::Display for ForestCompileError {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+/// Words the compiler handles itself, for tools like the language server.
+pub const BUILTIN_WORDS: &[&str] = &[
+    "dup",
+    "drop",
+    "+",
+    "-",
+    "*",
+    "/",
+    "str",
+    "<>",
+    ".",
+    "get",
+    "assoc",
+    "keys",
+    "vals",
+    "if",
+    "else",
+    "ifend",
+    "then",
+    "case",
+    "of",
+    "endof",
+    "endcase",
+    "&",
+    "|",
+    "!",
+    "=",
+    ">",
+    "<",
+    "[",
+    "]",
+    "break",
+    "do",
+    "loop",
+    "+loop",
+    "i",
+    "j",
+    "for",
+    "in",
+    "begin",
+    "until",
+    "while",
+    "repeat",
+    "swap",
+    "rot",
+    "-rot",
+    "over",
+    "nip",
+    "tuck",
+    "pick",
+    "roll",
+    "2dup",
+    "2drop",
+    "2swap",
+    "?dup",
+    "depth",
+    ">r",
+    "r>",
+    "r@",
+    "splat",
+    "include",
+    "eval",
+    "input",
+    "read-file",
+    "read-lines",
+    "write-file",
+    "append-file",
+    "file-exists?",
+    "delete-file",
+    "rename-file",
+    "list-dir",
+    "make-dir",
+    "args",
+    "getenv",
+    "setenv",
+    "cwd",
+    "script-path",
+    "assert",
+    "assert-eq",
+    "assert-stack",
+    "expect-error",
+    "::",
+    ":",
+    "=>",
+    "->",
+    ";",
+    "immediate",
+    "postpone",
+    "next-token",
+    "emit",
+    "emit-token",
+    "module",
+    "end",
+    "try",
+    "catch",
+    "throw",
+    "private",
+    "public",
+    "using",
+    "import",
+    "exit",
+    "exit-with",
+    "nil",
+    "{}",
+];
+
+/// Constructs that must be closed by a matching word.
+#[derive(PartialEq)]
+enum Block {
+    Module,
+    Try,
+    Catch,
+    Do,
+    For,
+    Begin,
+    While,
+    /// Where the `if` is in the program, and then where its `else` is.
+    If(usize),
+    Else(usize, usize),
+    /// How many `of`s the `case` has had so far.
+    Case(usize),
+    Of,
+}
+
+impl Block {
+    fn opener(&self) -> &'static str {
         match self {
-            Self::OutOfBoundsWord => write!(f, "Makeword (:) was last instruction!"),
+            Self::Module => "module",
+            Self::Try | Self::Catch => "try",
+            Self::Do => "do",
+            Self::For => "for",
+            Self::Begin => "begin",
+            Self::While => "while",
+            Self::If(_) => "if",
+            Self::Else(..) => "else",
+            Self::Case(_) => "case",
+            Self::Of => "of",
+        }
+    }
+
+    fn closer(&self) -> &'static str {
+        match self {
+            Self::Module | Self::Try | Self::Catch | Self::For => "end",
+            Self::Do => "loop",
+            Self::Begin => "until",
+            Self::While => "repeat",
+            Self::If(_) | Self::Else(..) => "ifend",
+            Self::Case(_) => "endcase",
+            Self::Of => "endof",
         }
     }
 }
 
-fn parse_number(inpt: &str) -> Option<fi> {
-    match inpt.parse::<i64>() {
-        Ok(i) => Some(fi::Push(ForestValue::Int(i))),
-        Err(_) => None,
+/// Pops the innermost block, which must be `expected` for `closer` to close it.
+fn close_block(
+    blocks: &mut Vec<Block>,
+    expected: Block,
+    closer: &str,
+    position: Position,
+) -> Result<(), ForestCompileError> {
+    match blocks.pop() {
+        Some(block) if block == expected => Ok(()),
+        Some(block) => Err(mismatched(closer, &block, position)),
+        None => Err(ForestCompileError::Unbalanced(closer.to_string(), position)),
+    }
+}
+
+fn mismatched(closer: &str, block: &Block, position: Position) -> ForestCompileError {
+    ForestCompileError::MismatchedBlock(closer.to_string(), block.opener().to_string(), position)
+}
+
+/// Finds the index of the `nth` innermost `do` loop, as the number of loops
+/// that keep two values on the return stack above it.
+fn do_depth(blocks: &[Block], nth: usize) -> Option<usize> {
+    blocks
+        .iter()
+        .rev()
+        .filter(|b| matches!(b, Block::Do | Block::For))
+        .enumerate()
+        .filter(|(_, b)| **b == Block::Do)
+        .nth(nth)
+        .map(|(depth, _)| depth)
+}
+
+/// Leaves the innermost loop if the flag on top of the stack is not nil,
+/// consuming the flag either way.
+const BREAK_IF: [fi; 5] = [fi::If, fi::Drop, fi::Break, fi::IfEnd, fi::Drop];
+
+impl ForestCompileError {
+    /// Where in the source the error is, if it is known.
+    pub fn position(&self) -> Option<Position> {
+        match self {
+            Self::UnterminatedString(p)
+            | Self::UnknownEscape(_, p)
+            | Self::InvalidEscape(_, p)
+            | Self::UnterminatedComment(p)
+            | Self::NumberOutOfRange(_, p)
+            | Self::MisplacedUnderscore(_, p)
+            | Self::Unbalanced(_, p)
+            | Self::MismatchedBlock(_, _, p)
+            | Self::UnterminatedBlock(_, _, p)
+            | Self::ImmediateWordFailed(_, _, p) => Some(*p),
+            _ => None,
+        }
     }
 }
 
-fn parse_string(inpt: &str) -> Option<fi> {
-    if inpt.len() > 1
-        && inpt.chars().nth(0).unwrap() == '\"'
-        && inpt.chars().nth_back(0).unwrap() == '\"'
+impl fmt::Display for ForestCompileError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::OutOfBoundsWord => write!(f, "Makeword (:) was last instruction!"),
+            Self::MissingName(kw) => write!(f, "`{kw}` must be followed by a name!"),
+            Self::MalformedImport => {
+                write!(f, "Imports must look like `import <module> as <alias>`!")
+            }
+            Self::MalformedFor => write!(f, "For loops must look like `for <key> <value> in`!"),
+            Self::Unbalanced(kw, p) => write!(f, "{p}: `{kw}` does not close anything!"),
+            Self::MismatchedBlock(kw, block, p) => write!(f, "{p}: `{kw}` can't close `{block}`!"),
+            Self::UnterminatedBlock(kw, closer, p) => {
+                write!(f, "{p}: `{kw}` is never closed with `{closer}`!")
+            }
+            Self::CatchOutsideTry => write!(f, "`catch` must be inside a `try`!"),
+            Self::TryWithoutCatch => write!(f, "`try` must have a `catch` before its `end`!"),
+            Self::UnterminatedString(p) => write!(f, "{p}: String is never closed!"),
+            Self::UnknownEscape(c, p) => write!(f, "{p}: Unknown escape `\\{c}`!"),
+            Self::InvalidEscape(e, p) => write!(f, "{p}: Invalid escape `{e}`!"),
+            Self::UnterminatedComment(p) => write!(f, "{p}: Comment is never closed with `)`!"),
+            Self::NumberOutOfRange(n, p) => write!(f, "{p}: `{n}` does not fit in an int!"),
+            Self::MisplacedUnderscore(n, p) => {
+                write!(
+                    f,
+                    "{p}: Underscores in `{n}` must each go between two digits!"
+                )
+            }
+            Self::MisplacedImmediate => {
+                write!(f, "`immediate` must come right after the `;` of a word!")
+            }
+            Self::ImmediateWordFailed(w, e, p) => {
+                write!(f, "{p}: Immediate word `{w}` failed, reason:\n> {e}")
+            }
+        }
+    }
+}
+
+/// Parses ints like `-42`, `0xFF`, `0b1010`, `0o17` and `1_000_000`. Tokens
+/// that are only made of digits but don't fit in an i64 are errors, while
+/// other tokens (like `2dup`) aren't numbers at all.
+fn parse_number(inpt: &str, position: Position) -> Result<Option<fi>, ForestCompileError> {
+    let (sign, unsigned) = match inpt.strip_prefix('-') {
+        Some(rest) => ("-", rest),
+        None => ("", inpt.strip_prefix('+').unwrap_or(inpt)),
+    };
+    let (radix, digits) = match unsigned.get(..2) {
+        Some("0x" | "0X") => (16, &unsigned[2..]),
+        Some("0b" | "0B") => (2, &unsigned[2..]),
+        Some("0o" | "0O") => (8, &unsigned[2..]),
+        _ => (10, unsigned),
+    };
+    // Decimal numbers start with a digit, so that `_1` can still be a word.
+    if !digits.chars().any(|c| c.is_digit(radix))
+        || !digits.chars().all(|c| c.is_digit(radix) || c == '_')
+        || radix == 10 && digits.starts_with('_')
     {
-        Some(fi::Push(ForestValue::String({
-            let escaped: &str = &inpt[1..(inpt.len() - 1)];
-            escaped
-                .replace("\\r", "\r")
-                .replace("\\n", "\n")
-                .replace("\\t", "\t")
-                .replace("\\\\", "\\")
-                .replace("\\\"", "\"")
-        })))
-    } else {
-        None
+        return Ok(None);
+    }
+    if digits.starts_with('_') || digits.ends_with('_') || digits.contains("__") {
+        return Err(ForestCompileError::MisplacedUnderscore(
+            inpt.to_string(),
+            position,
+        ));
+    }
+    let digits: String = digits.chars().filter(|c| *c != '_').collect();
+    match i64::from_str_radix(&format!("{sign}{digits}"), radix) {
+        Ok(i) => Ok(Some(fi::Push(ForestValue::Int(i)))),
+        Err(_) => Err(ForestCompileError::NumberOutOfRange(
+            inpt.to_string(),
+            position,
+        )),
     }
 }
 
@@ -56,97 +327,647 @@ fn parse_table(inpt: &str) -> Option<fi> {
     }
 }
 
-pub fn compile(programstr: &str) -> Result<Vec<fi>, ForestCompileError> {
-    let mut wrapped = false;
-    let mut escaping = false;
-    let mut tokens = programstr
-        .split(|c: char| {
-            if c == '"' && !escaping {
-                wrapped = !wrapped;
-            }
-            if escaping {
-                escaping = false;
+/// Something left to compile.
+enum Pending {
+    Token(Token),
+    /// A value emitted by an immediate word, compiled to a push.
+    Value(ForestValue),
+}
+
+/// The tokens left to compile. Whatever immediate words emit is compiled
+/// before the rest of the source.
+struct TokenStream {
+    pending: VecDeque<Pending>,
+    source: vec::IntoIter<Token>,
+}
+
+impl TokenStream {
+    fn next_pending(&mut self) -> Option<Pending> {
+        self.pending.pop_front().or_else(|| {
+            self.source
+                .find(|t| !matches!(t.kind, TokenKind::Comment(_)))
+                .map(Pending::Token)
+        })
+    }
+
+    /// Takes the next token. Emitted values aren't tokens, so they are left
+    /// alone and end up being compiled on their own.
+    fn next(&mut self) -> Option<Token> {
+        match self.pending.front() {
+            Some(Pending::Value(_)) => None,
+            _ => match self.next_pending() {
+                Some(Pending::Token(t)) => Some(t),
+                _ => None,
+            },
+        }
+    }
+
+    fn next_raw(&mut self) -> Option<String> {
+        self.next().map(|t| t.raw)
+    }
+
+    /// Queues what an immediate word emitted. Its tokens are given the
+    /// position of the immediate word, since they aren't in the source.
+    fn push_emitted(
+        &mut self,
+        emitted: Vec<Emitted>,
+        position: Position,
+    ) -> Result<(), ForestCompileError> {
+        let mut items = VecDeque::new();
+        for e in emitted {
+            match e {
+                Emitted::Source(source) => items.extend(
+                    lex(&source)?
+                        .into_iter()
+                        .filter(|t| !matches!(t.kind, TokenKind::Comment(_)))
+                        .map(|t| Pending::Token(Token { position, ..t })),
+                ),
+                Emitted::Value(v) => items.push_back(Pending::Value(v)),
             }
-            if c == '\\' {
-                escaping = true;
+        }
+        items.append(&mut self.pending);
+        self.pending = items;
+        Ok(())
+    }
+}
+
+/// Runs immediate words while compiling. Every word defined so far in the
+/// source is also defined in its runtime, so immediate words can use them.
+#[derive(Default)]
+struct CompileTime {
+    /// Only made once an immediate word runs.
+    runtime: Option<ForestRuntime>,
+    /// Definitions that haven't been given to the runtime yet.
+    definitions: Vec<fi>,
+    /// Every definition so far, for the files that include this one.
+    exported: Vec<fi>,
+    immediates: HashSet<String>,
+    /// The file being compiled, which includes are looked for next to.
+    path: Option<PathBuf>,
+    /// Files whose immediate words were already imported, or that are being
+    /// compiled, so each is only imported once.
+    imported: HashSet<PathBuf>,
+    /// Whether the source is only being checked, so no immediate word runs.
+    checking: bool,
+}
+
+impl CompileTime {
+    /// Imports the immediate words of a file included with a string literal,
+    /// with the words they can use, by compiling it too. Files that can't be
+    /// found or compiled are left for the `include` to report when it runs.
+    fn import(&mut self, filename: &str) {
+        let (path, source) = match bundled_library(filename) {
+            Some(source) => (PathBuf::from(format!("<{filename}>")), source.to_string()),
+            None => match resolve_include(self.path.as_deref(), filename) {
+                Some(p) => match fs::read_to_string(&p) {
+                    Ok(source) => (p, source),
+                    Err(_) => return,
+                },
+                None => return,
+            },
+        };
+        if !self.imported.insert(path.clone()) {
+            return;
+        }
+        let mut included = CompileTime {
+            path: Some(path),
+            imported: std::mem::take(&mut self.imported),
+            checking: self.checking,
+            ..CompileTime::default()
+        };
+        let compiled = compile_with(&source, &mut included);
+        self.imported = included.imported;
+        if compiled.is_ok() {
+            self.definitions.extend_from_slice(&included.exported);
+            self.exported.extend(included.exported);
+            self.immediates.extend(included.immediates);
+        }
+    }
+
+    fn run(
+        &mut self,
+        name: &str,
+        tokens: &mut TokenStream,
+        position: Position,
+    ) -> Result<(), ForestCompileError> {
+        let fresh = self.runtime.is_none();
+        let runtime = self
+            .runtime
+            .get_or_insert_with(ForestRuntime::new_compile_time);
+        let mut instrs = std::mem::take(&mut self.definitions);
+        instrs.push(fi::InvokeWord(name.to_string()));
+        runtime.push_instrs(&instrs);
+        // Pushed last so that it runs first, before the definitions.
+        if fresh {
+            runtime.load_prelude();
+        }
+        loop {
+            match runtime.run_until(|_| false) {
+                ForestStatus::WaitingForToken => {
+                    runtime.provide_token(tokens.next_raw().as_deref())
+                }
+                ForestStatus::WaitingForInput => runtime.provide_input(None),
+                ForestStatus::Errored(e) => {
+                    return Err(ForestCompileError::ImmediateWordFailed(
+                        name.to_string(),
+                        e.to_string(),
+                        position,
+                    ));
+                }
+                ForestStatus::Halted(_) | ForestStatus::Running => break,
             }
-            c.is_whitespace() && !wrapped
-        })
-        .filter(|s| s.len() != 0);
+        }
+        tokens.push_emitted(runtime.take_emitted(), position)
+    }
+}
+
+/// Compiles a program. `path` is the file it was read from, if any, which
+/// files it includes with a string literal are looked for next to, to import
+/// their immediate words.
+pub fn compile(programstr: &str, path: Option<&Path>) -> Result<Vec<fi>, ForestCompileError> {
+    compile_mapped(programstr, path).map(|(program, _)| program)
+}
+
+/// Compiles like `compile`, also returning where in the source each
+/// instruction came from. Instructions compiled from what an immediate word
+/// emitted point at the immediate word.
+pub fn compile_mapped(
+    programstr: &str,
+    path: Option<&Path>,
+) -> Result<(Vec<fi>, Vec<Position>), ForestCompileError> {
+    let mut compile_time = CompileTime {
+        path: path.map(Path::to_path_buf),
+        imported: path.map(Path::to_path_buf).into_iter().collect(),
+        ..CompileTime::default()
+    };
+    compile_with(programstr, &mut compile_time)
+}
+
+/// Checks that a program compiles without running any immediate word, for
+/// tools that look at code they shouldn't run. What an immediate word
+/// emits can't be known without running it, so the rest of the source after
+/// the first one is not checked. Returns what was compiled before it, like
+/// `compile_mapped`, which may leave blocks open.
+pub fn check(
+    programstr: &str,
+    path: Option<&Path>,
+) -> Result<(Vec<fi>, Vec<Position>), ForestCompileError> {
+    let mut compile_time = CompileTime {
+        path: path.map(Path::to_path_buf),
+        imported: path.map(Path::to_path_buf).into_iter().collect(),
+        checking: true,
+        ..CompileTime::default()
+    };
+    compile_with(programstr, &mut compile_time)
+}
+
+fn compile_with(
+    programstr: &str,
+    compile_time: &mut CompileTime,
+) -> Result<(Vec<fi>, Vec<Position>), ForestCompileError> {
+    let mut tokens = TokenStream {
+        pending: VecDeque::new(),
+        source: lex(programstr)?.into_iter(),
+    };
     let mut program: Vec<fi> = Vec::new();
+    let mut blocks: Vec<Block> = Vec::new();
+    // Where each of the blocks was opened.
+    let mut openings: Vec<Position> = Vec::new();
+    // Where the definitions being compiled start, innermost last.
+    let mut definition_starts: Vec<usize> = Vec::new();
+    // Where the last top-level definition starts and ends.
+    let mut last_definition: Option<(usize, usize)> = None;
+    let mut positions: Vec<Position> = Vec::new();
+    // The position of the last token read, given to whatever it compiled to.
+    let mut position = Position { line: 1, column: 1 };
     'compilation: loop {
-        if let Some(tk) = tokens.next() {
-            match tk {
-                "dup" => program.push(fi::Duplicate),
-                "drop" => program.push(fi::Drop),
-                "+" => program.push(fi::Add),
-                "-" => program.push(fi::Subtract),
-                "*" => program.push(fi::Multiply),
-                "/" => program.push(fi::Divide),
-                "str" => program.push(fi::Stringify),
-                "<>" => program.push(fi::Concatenate),
-                "." => program.push(fi::Print),
-                "get" => program.push(fi::Get),
-                "assoc" => program.push(fi::Associate),
-                "keys" => program.push(fi::Keys),
-                "vals" => program.push(fi::Values),
-                "if" => program.push(fi::If),
-                "ifend" => program.push(fi::IfEnd),
-                "&" => program.push(fi::And),
-                "|" => program.push(fi::Or),
-                "!" => program.push(fi::Not),
-                "=" => program.push(fi::Eq),
-                ">" => program.push(fi::GreaterThan),
-                "<" => program.push(fi::LessThan),
-                "[" => program.push(fi::Loop),
-                "]" => program.push(fi::LoopEnd),
-                "break" => program.push(fi::Break),
-                "swap" => program.push(fi::Swap),
-                "rot" => program.push(fi::Rotate),
-                "splat" => program.push(fi::Splat),
-                "include" => program.push(fi::Include),
-                "::" => {
-                    if let Some(tk) = tokens.next() {
-                        program.push(fi::MakeWord(tk.to_string()))
-                    } else {
-                        return Err(ForestCompileError::OutOfBoundsWord);
-                    }
+        positions.resize(program.len(), position);
+        openings.truncate(blocks.len());
+        openings.resize(blocks.len(), position);
+        let token = match tokens.next_pending() {
+            Some(Pending::Token(token)) => token,
+            Some(Pending::Value(v)) => {
+                program.push(fi::Push(v));
+                continue 'compilation;
+            }
+            None => break 'compilation,
+        };
+        position = token.position;
+        let tk = match &token.kind {
+            TokenKind::Str(s) => {
+                program.push(fi::Push(ForestValue::String(s.clone())));
+                continue 'compilation;
+            }
+            TokenKind::Char(c) => {
+                program.push(fi::Push(ForestValue::Int(*c as i64)));
+                continue 'compilation;
+            }
+            TokenKind::Word(w) => w.as_str(),
+            TokenKind::Comment(_) => unreachable!(),
+        };
+        if compile_time.immediates.contains(tk) {
+            if compile_time.checking {
+                return Ok((program, positions));
+            }
+            compile_time.run(tk, &mut tokens, position)?;
+            continue 'compilation;
+        }
+        match tk {
+            "dup" => program.push(fi::Duplicate),
+            "drop" => program.push(fi::Drop),
+            "+" => program.push(fi::Add),
+            "-" => program.push(fi::Subtract),
+            "*" => program.push(fi::Multiply),
+            "/" => program.push(fi::Divide),
+            "str" => program.push(fi::Stringify),
+            "<>" => program.push(fi::Concatenate),
+            "." => program.push(fi::Print),
+            "get" => program.push(fi::Get),
+            "assoc" => program.push(fi::Associate),
+            "keys" => program.push(fi::Keys),
+            "vals" => program.push(fi::Values),
+            "if" => {
+                blocks.push(Block::If(program.len()));
+                program.push(fi::If)
+            }
+            "else" => match blocks.pop() {
+                Some(Block::If(at)) => {
+                    blocks.push(Block::Else(at, program.len()));
+                    program.push(fi::Else)
                 }
-                ":" => {
-                    if let Some(tk) = tokens.next() {
-                        program.push(fi::MakeWordVar(tk.to_string()))
-                    } else {
-                        return Err(ForestCompileError::OutOfBoundsWord);
-                    }
+                Some(block) => return Err(mismatched(tk, &block, position)),
+                None => return Err(ForestCompileError::Unbalanced(tk.to_string(), position)),
+            },
+            "ifend" => match blocks.pop() {
+                Some(Block::If(_) | Block::Else(..)) => program.push(fi::IfEnd),
+                Some(block) => return Err(mismatched(tk, &block, position)),
+                None => return Err(ForestCompileError::Unbalanced(tk.to_string(), position)),
+            },
+            // `then` closes the consuming form of `if`, which drops the flag
+            // at the start of whichever branch runs.
+            "then" => match blocks.pop() {
+                Some(Block::If(at)) => {
+                    program.insert(at + 1, fi::Drop);
+                    positions.insert(at + 1, positions[at]);
+                    program.extend([fi::Else, fi::Drop, fi::IfEnd]);
                 }
-                "=>" => {
-                    if let Some(tk) = tokens.next() {
-                        program.push(fi::Set(tk.to_string()))
-                    } else {
-                        return Err(ForestCompileError::OutOfBoundsWord);
-                    }
+                Some(Block::Else(at, else_at)) => {
+                    program.insert(else_at + 1, fi::Drop);
+                    positions.insert(else_at + 1, positions[else_at]);
+                    program.insert(at + 1, fi::Drop);
+                    positions.insert(at + 1, positions[at]);
+                    program.push(fi::IfEnd);
+                }
+                Some(block) => return Err(mismatched(tk, &block, position)),
+                None => return Err(ForestCompileError::Unbalanced(tk.to_string(), position)),
+            },
+            // Each `of` compares its value to the one given to `case`, and
+            // its branch ends up nested in the else branch of the one before.
+            "case" => blocks.push(Block::Case(0)),
+            "of" => match blocks.pop() {
+                Some(Block::Case(ofs)) => {
+                    blocks.extend([Block::Case(ofs + 1), Block::Of]);
+                    program.extend([fi::Over, fi::Eq, fi::If, fi::Drop, fi::Drop]);
+                }
+                Some(block) => return Err(mismatched(tk, &block, position)),
+                None => return Err(ForestCompileError::Unbalanced(tk.to_string(), position)),
+            },
+            "endof" => {
+                close_block(&mut blocks, Block::Of, tk, position)?;
+                program.extend([fi::Else, fi::Drop]);
+            }
+            "endcase" => match blocks.pop() {
+                Some(Block::Case(ofs)) => {
+                    program.push(fi::Drop);
+                    program.extend(std::iter::repeat_n(fi::IfEnd, ofs));
+                }
+                Some(block) => return Err(mismatched(tk, &block, position)),
+                None => return Err(ForestCompileError::Unbalanced(tk.to_string(), position)),
+            },
+            "&" => program.push(fi::And),
+            "|" => program.push(fi::Or),
+            "!" => program.push(fi::Not),
+            "=" => program.push(fi::Eq),
+            ">" => program.push(fi::GreaterThan),
+            "<" => program.push(fi::LessThan),
+            "[" => program.push(fi::Loop),
+            "]" => program.push(fi::LoopEnd),
+            "break" => program.push(fi::Break),
+            // The limit and index of a `do` loop live on the return stack,
+            // with the index on top.
+            "do" => {
+                blocks.push(Block::Do);
+                program.extend([fi::Swap, fi::ToReturn, fi::ToReturn, fi::Loop]);
+            }
+            "loop" | "+loop" => {
+                close_block(&mut blocks, Block::Do, tk, position)?;
+                if tk == "loop" {
+                    program.push(fi::Push(ForestValue::Int(1)));
+                }
+                program.push(fi::LoopStep);
+                program.extend(BREAK_IF);
+                program.extend([fi::LoopEnd, fi::FromReturn, fi::FromReturn, fi::TwoDrop]);
+            }
+            // `i` and `j` are only loop indices inside `do` loops, so they
+            // can still be used as names elsewhere.
+            "i" | "j" => program.push(match do_depth(&blocks, (tk == "j") as usize) {
+                Some(depth) => fi::LoopIndex(depth),
+                None => fi::InvokeWord(tk.to_string()),
+            }),
+            // A for loop keeps the table and the index of the next pair on
+            // the return stack. Its variables are only bound inside it, and
+            // whatever they hid is put back when it ends.
+            "for" => match (
+                tokens.next_raw().as_deref(),
+                tokens.next_raw().as_deref(),
+                tokens.next_raw().as_deref(),
+            ) {
+                (Some(key), Some(value), Some("in")) => {
+                    blocks.push(Block::For);
+                    program.extend([
+                        fi::Shadow(key.to_string()),
+                        fi::Shadow(value.to_string()),
+                        fi::ToReturn,
+                        fi::Push(ForestValue::Int(0)),
+                        fi::ToReturn,
+                        fi::Loop,
+                        fi::ForEachNext,
+                    ]);
+                    program.extend(BREAK_IF);
+                    program.extend([fi::SetVar(value.to_string()), fi::SetVar(key.to_string())]);
+                }
+                _ => return Err(ForestCompileError::MalformedFor),
+            },
+            "begin" => {
+                blocks.push(Block::Begin);
+                program.push(fi::Loop);
+            }
+            "until" => {
+                close_block(&mut blocks, Block::Begin, tk, position)?;
+                program.extend(BREAK_IF);
+                program.push(fi::LoopEnd);
+            }
+            "while" => {
+                close_block(&mut blocks, Block::Begin, tk, position)?;
+                blocks.push(Block::While);
+                program.push(fi::Not);
+                program.extend(BREAK_IF);
+            }
+            "repeat" => {
+                close_block(&mut blocks, Block::While, tk, position)?;
+                program.push(fi::LoopEnd);
+            }
+            "swap" => program.push(fi::Swap),
+            "rot" => program.push(fi::Rotate),
+            "-rot" => program.push(fi::RotateBack),
+            "over" => program.push(fi::Over),
+            "nip" => program.push(fi::Nip),
+            "tuck" => program.push(fi::Tuck),
+            "pick" => program.push(fi::Pick),
+            "roll" => program.push(fi::Roll),
+            "2dup" => program.push(fi::TwoDup),
+            "2drop" => program.push(fi::TwoDrop),
+            "2swap" => program.push(fi::TwoSwap),
+            "?dup" => program.push(fi::DupIfTrue),
+            "depth" => program.push(fi::Depth),
+            ">r" => program.push(fi::ToReturn),
+            "r>" => program.push(fi::FromReturn),
+            "r@" => program.push(fi::FetchReturn),
+            "splat" => program.push(fi::Splat),
+            "include" => {
+                if let Some(fi::Push(ForestValue::String(filename))) = program.last() {
+                    compile_time.import(&filename.clone());
                 }
-                "->" => {
-                    if let Some(tk) = tokens.next() {
-                        program.push(fi::SetVar(tk.to_string()))
-                    } else {
-                        return Err(ForestCompileError::OutOfBoundsWord);
+                program.push(fi::Include)
+            }
+            "eval" => program.push(fi::Eval),
+            "input" => program.push(fi::Input),
+            "read-file" => program.push(fi::ReadFile),
+            "read-lines" => program.push(fi::ReadLines),
+            "write-file" => program.push(fi::WriteFile),
+            "append-file" => program.push(fi::AppendFile),
+            "file-exists?" => program.push(fi::FileExists),
+            "delete-file" => program.push(fi::DeleteFile),
+            "rename-file" => program.push(fi::RenameFile),
+            "list-dir" => program.push(fi::ListDir),
+            "make-dir" => program.push(fi::MakeDir),
+            "args" => program.push(fi::Args),
+            "getenv" => program.push(fi::GetEnv),
+            "setenv" => program.push(fi::SetEnv),
+            "cwd" => program.push(fi::Cwd),
+            "script-path" => program.push(fi::ScriptPath),
+            "assert" => program.push(fi::Assert),
+            "assert-eq" => program.push(fi::AssertEq),
+            "assert-stack" => program.push(fi::AssertStack),
+            "expect-error" => program.push(fi::ExpectError),
+            "::" => {
+                if let Some(tk) = tokens.next_raw() {
+                    definition_starts.push(program.len());
+                    program.push(fi::MakeWord(tk))
+                } else {
+                    return Err(ForestCompileError::OutOfBoundsWord);
+                }
+            }
+            ":" => {
+                if let Some(tk) = tokens.next_raw() {
+                    definition_starts.push(program.len());
+                    program.push(fi::MakeWordVar(tk))
+                } else {
+                    return Err(ForestCompileError::OutOfBoundsWord);
+                }
+            }
+            "=>" => {
+                if let Some(tk) = tokens.next_raw().as_deref() {
+                    program.push(fi::Set(tk.to_string()))
+                } else {
+                    return Err(ForestCompileError::OutOfBoundsWord);
+                }
+            }
+            "->" => {
+                if let Some(tk) = tokens.next_raw().as_deref() {
+                    program.push(fi::SetVar(tk.to_string()))
+                } else {
+                    return Err(ForestCompileError::OutOfBoundsWord);
+                }
+            }
+            ";" => {
+                program.push(fi::EndWord);
+                if let Some(start) = definition_starts.pop()
+                    && definition_starts.is_empty()
+                {
+                    compile_time
+                        .definitions
+                        .extend_from_slice(&program[start..]);
+                    compile_time.exported.extend_from_slice(&program[start..]);
+                    last_definition = Some((start, program.len()));
+                }
+            }
+            // Immediate words only exist while compiling, so they are
+            // taken out of the program.
+            "immediate" => match last_definition.take() {
+                Some((start, end)) if end == program.len() => {
+                    if let fi::MakeWord(name) | fi::MakeWordVar(name) = &program[start] {
+                        compile_time.immediates.insert(name.clone());
                     }
+                    program.truncate(start);
+                    positions.truncate(start);
+                }
+                _ => return Err(ForestCompileError::MisplacedImmediate),
+            },
+            "postpone" => match tokens.next_raw() {
+                Some(tk) => program.extend([fi::Push(ForestValue::String(tk)), fi::EmitToken]),
+                None => return Err(ForestCompileError::MissingName(tk.to_string())),
+            },
+            "next-token" => program.push(fi::NextToken),
+            "emit" => program.push(fi::Emit),
+            "emit-token" => program.push(fi::EmitToken),
+            "module" => {
+                if let Some(tk) = tokens.next_raw().as_deref() {
+                    blocks.push(Block::Module);
+                    program.push(fi::Module(tk.to_string()))
+                } else {
+                    return Err(ForestCompileError::MissingName(tk.to_string()));
+                }
+            }
+            "end" => match blocks.pop() {
+                Some(Block::Module) => program.push(fi::ExitScope),
+                Some(Block::Try) => return Err(ForestCompileError::TryWithoutCatch),
+                Some(Block::Catch) => program.push(fi::EndTry),
+                Some(Block::For) => program.extend([
+                    fi::LoopEnd,
+                    fi::FromReturn,
+                    fi::FromReturn,
+                    fi::TwoDrop,
+                    fi::Unshadow,
+                    fi::Unshadow,
+                ]),
+                Some(block) => return Err(mismatched(tk, &block, position)),
+                None => return Err(ForestCompileError::Unbalanced(tk.to_string(), position)),
+            },
+            "try" => {
+                blocks.push(Block::Try);
+                program.push(fi::Try)
+            }
+            "catch" => match blocks.last() {
+                Some(Block::Try) => {
+                    blocks.pop();
+                    blocks.push(Block::Catch);
+                    program.push(fi::Catch)
+                }
+                _ => return Err(ForestCompileError::CatchOutsideTry),
+            },
+            "throw" => program.push(fi::Throw),
+            "private" => program.push(fi::Private),
+            "public" => program.push(fi::Public),
+            "using" => {
+                if let Some(tk) = tokens.next_raw().as_deref() {
+                    program.push(fi::Using(tk.to_string()))
+                } else {
+                    return Err(ForestCompileError::MissingName(tk.to_string()));
                 }
-                ";" => program.push(fi::EndWord),
-                "exit" => program.push(fi::Exit),
-                s => program.push({
-                    parse_number(s)
-                        .or_else(|| parse_string(s))
-                        .or_else(|| parse_nil(s))
-                        .or_else(|| parse_table(s))
-                        .or_else(|| Some(fi::InvokeWord(s.to_string())))
-                        .unwrap()
-                }),
-            }
-        } else {
-            break 'compilation;
+            }
+            "import" => match (
+                tokens.next_raw().as_deref(),
+                tokens.next_raw().as_deref(),
+                tokens.next_raw().as_deref(),
+            ) {
+                (Some(module), Some("as"), Some(alias)) => {
+                    program.push(fi::Alias(alias.to_string(), module.to_string()))
+                }
+                _ => return Err(ForestCompileError::MalformedImport),
+            },
+            "exit" => program.push(fi::Exit),
+            "exit-with" => program.push(fi::ExitWith),
+            s => program.push({
+                parse_number(s, token.position)?
+                    .or_else(|| parse_nil(s))
+                    .or_else(|| parse_table(s))
+                    .or_else(|| Some(fi::InvokeWord(s.to_string())))
+                    .unwrap()
+            }),
+        }
+    }
+    if let Some(block) = blocks.pop() {
+        return Err(ForestCompileError::UnterminatedBlock(
+            block.opener().to_string(),
+            block.closer().to_string(),
+            openings.get(blocks.len()).copied().unwrap_or(position),
+        ));
+    }
+    positions.resize(program.len(), position);
+    Ok((program, positions))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn builtin_words_are_compiled_by_the_compiler() {
+        for word in BUILTIN_WORDS {
+            // Words that open or close blocks, or need a name after them, don't
+            // compile on their own, which still shows the compiler knows them.
+            let invokes = |source: &str| {
+                compile(source, None).is_ok_and(|p| {
+                    p.iter()
+                        .any(|i| matches!(i, fi::InvokeWord(w) if w == word))
+                })
+            };
+            // `i` and `j` are only loop indices inside `do` loops, and `in`
+            // is only a keyword in the header of a for loop.
+            let source = match *word {
+                "in" => "{} for k v in end".to_string(),
+                _ => format!("1 0 do 1 0 do {word} loop loop"),
+            };
+            assert!(
+                !invokes(word) || !invokes(&source) && compile(&source, None).is_ok(),
+                "`{word}` compiles to an invocation, so it isn't a builtin"
+            );
         }
     }
-    Ok(program)
+
+    #[test]
+    fn underscores_only_go_between_digits() {
+        let number = |s: &str| parse_number(s, Position { line: 1, column: 1 });
+        assert!(matches!(
+            number("1_000"),
+            Ok(Some(fi::Push(ForestValue::Int(1000))))
+        ));
+        for misplaced in ["1_", "1__0", "0x_ff", "0b1_", "-1_"] {
+            assert!(
+                matches!(
+                    number(misplaced),
+                    Err(ForestCompileError::MisplacedUnderscore(..))
+                ),
+                "`{misplaced}` compiled"
+            );
+        }
+        assert!(matches!(number("_1"), Ok(None)));
+    }
+
+    #[test]
+    fn checking_runs_no_immediate_words() {
+        let source = ": boom \"boom\" throw ; immediate boom";
+        assert!(compile(source, None).is_err());
+        assert!(check(source, None).is_ok());
+        assert!(check("1 0 do", None).is_err());
+        // Only what comes before the immediate word is compiled.
+        let source = ": unless postpone ! postpone if ; immediate 1 . 0 unless 2 . ifend";
+        let (program, positions) = check(source, None).unwrap_or_else(|e| panic!("{e}"));
+        assert_eq!(
+            program.iter().map(ToString::to_string).collect::<Vec<_>>(),
+            ["Push 1", "Print", "Push 0"]
+        );
+        assert_eq!(positions.len(), program.len());
+    }
+
+    #[test]
+    fn immediate_words_can_use_the_standard_library() {
+        let source = ": seven-mod-three ( -- ) 7 3 % abs emit ; immediate seven-mod-three";
+        let program = compile(source, None).unwrap_or_else(|e| panic!("{e}"));
+        assert_eq!(
+            program.iter().map(ToString::to_string).collect::<Vec<_>>(),
+            ["Push 1"]
+        );
+    }
 }
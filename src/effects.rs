@@ -0,0 +1,837 @@
+use crate::analysis::{Analysis, DefinitionKind, Source, visible};
+use crate::compile::check;
+use crate::forest_runtime::{ForestInstruction as fi, ForestValue};
+use crate::lexer::Position;
+use crate::test_runner::collect_files;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// How many times the programs are checked at most while the types of
+/// variables are still being worked out.
+const MAX_PASSES: usize = 8;
+
+/// What is known about the type of a value.
+#[derive(Clone, Copy, PartialEq)]
+pub enum Ty {
+    Int,
+    String,
+    Table,
+    Nil,
+    Any,
+}
+
+impl Ty {
+    fn of(value: &ForestValue) -> Ty {
+        match value {
+            ForestValue::Nil => Ty::Nil,
+            ForestValue::Int(_) => Ty::Int,
+            ForestValue::String(_) => Ty::String,
+            ForestValue::Table(_) => Ty::Table,
+        }
+    }
+
+    /// The type of a value that may have come from either of two places.
+    fn join(self, other: Ty) -> Ty {
+        if self == other { self } else { Ty::Any }
+    }
+}
+
+impl fmt::Display for Ty {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Ty::Int => write!(f, "an int"),
+            Ty::String => write!(f, "a string"),
+            Ty::Table => write!(f, "a table"),
+            Ty::Nil => write!(f, "nil"),
+            Ty::Any => write!(f, "anything"),
+        }
+    }
+}
+
+/// What an instruction needs a value it takes to be.
+#[derive(Clone, Copy)]
+enum Need {
+    Any,
+    Is(Ty),
+    /// Table keys can be anything but nil.
+    NotNil,
+}
+
+impl Need {
+    /// Whether a value of type `ty` is definitely not what is needed.
+    fn refuses(self, ty: Ty) -> bool {
+        match self {
+            Need::Any => false,
+            Need::Is(t) => ty != Ty::Any && ty != t,
+            Need::NotNil => ty == Ty::Nil,
+        }
+    }
+}
+
+impl fmt::Display for Need {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Need::Any => write!(f, "anything"),
+            Need::Is(t) => write!(f, "{t}"),
+            Need::NotNil => write!(f, "something other than nil"),
+        }
+    }
+}
+
+/// How many values something takes from the stack, and the types of the
+/// values it leaves, bottom-most first.
+#[derive(Clone, PartialEq)]
+pub struct Effect {
+    pub inputs: usize,
+    pub outputs: Vec<Ty>,
+}
+
+impl Effect {
+    /// Reads a stack-effect comment like `( a b -- c )`, counting the names
+    /// on either side of the `--`. Effects with a `...` in them take or leave
+    /// a varying number of values, so they can't be checked.
+    pub fn parse(comment: &str) -> Option<Effect> {
+        let inner = comment.strip_prefix('(')?.strip_suffix(')')?;
+        let (inputs, outputs) = inner.split_once("--")?;
+        if inner.contains("...") {
+            return None;
+        }
+        Some(Effect {
+            inputs: inputs.split_whitespace().count(),
+            outputs: vec![Ty::Any; outputs.split_whitespace().count()],
+        })
+    }
+}
+
+/// What an instruction does to the stack, when that doesn't depend on the
+/// values it is given.
+enum Action {
+    /// Takes values needing to be these, bottom-most first, and leaves values
+    /// of these types.
+    Typed(&'static [Need], &'static [Ty]),
+    /// Takes this many values and leaves copies of the ones at these
+    /// indices, counting from the bottom-most one taken.
+    Shuffle(usize, Vec<usize>),
+}
+
+const INT: Need = Need::Is(Ty::Int);
+const STRING: Need = Need::Is(Ty::String);
+const TABLE: Need = Need::Is(Ty::Table);
+
+/// The action of an instruction. Control flow, words, and instructions
+/// whose effect depends on the values they are given have none.
+fn action(instr: &fi) -> Option<Action> {
+    use Action::*;
+    use Need::Any;
+    Some(match instr {
+        fi::LoopIndex(_) | fi::Depth => Typed(&[], &[Ty::Int]),
+        fi::FromReturn | fi::FetchReturn | fi::Input | fi::NextToken => Typed(&[], &[Ty::Any]),
+        fi::ScriptPath => Typed(&[], &[Ty::Any]),
+        fi::Args => Typed(&[], &[Ty::Table]),
+        fi::Cwd => Typed(&[], &[Ty::String]),
+        fi::Print | fi::ToReturn | fi::Set(_) | fi::SetVar(_) => Typed(&[Any], &[]),
+        fi::Assert | fi::Emit => Typed(&[Any], &[]),
+        fi::AssertStack => Typed(&[INT], &[]),
+        fi::Include | fi::ExpectError | fi::EmitToken => Typed(&[STRING], &[]),
+        fi::DeleteFile | fi::MakeDir => Typed(&[STRING], &[]),
+        fi::Add | fi::Subtract | fi::Multiply | fi::Divide => Typed(&[INT, INT], &[Ty::Int]),
+        fi::Concatenate => Typed(&[STRING, STRING], &[Ty::String]),
+        fi::And | fi::Or | fi::Eq => Typed(&[Any, Any], &[Ty::Any]),
+        fi::GreaterThan | fi::LessThan => Typed(&[INT, INT], &[Ty::Any]),
+        fi::Stringify => Typed(&[Any], &[Ty::String]),
+        fi::Not => Typed(&[Any], &[Ty::Any]),
+        fi::LoopStep => Typed(&[INT], &[Ty::Any]),
+        fi::Keys | fi::Values => Typed(&[TABLE], &[Ty::Table]),
+        fi::ReadFile => Typed(&[STRING], &[Ty::String]),
+        fi::ReadLines | fi::ListDir => Typed(&[STRING], &[Ty::Table]),
+        fi::FileExists | fi::GetEnv => Typed(&[STRING], &[Ty::Any]),
+        fi::Get => Typed(&[TABLE, Need::NotNil], &[Ty::Table, Ty::Any]),
+        fi::Associate => Typed(&[TABLE, Need::NotNil, Any], &[Ty::Table]),
+        fi::AssertEq => Typed(&[Any, Any], &[]),
+        fi::WriteFile | fi::AppendFile | fi::RenameFile | fi::SetEnv => {
+            Typed(&[STRING, STRING], &[])
+        }
+        fi::Module(_) | fi::EnterScope(_) | fi::ExitScope | fi::Private | fi::Public => {
+            Typed(&[], &[])
+        }
+        fi::Using(_) | fi::Alias(..) | fi::EndInclude => Typed(&[], &[]),
+        fi::Shadow(_) | fi::Unshadow => Typed(&[], &[]),
+        fi::Duplicate => Shuffle(1, vec![0, 0]),
+        fi::Drop => Shuffle(1, vec![]),
+        fi::Swap => Shuffle(2, vec![1, 0]),
+        fi::Rotate => Shuffle(3, vec![2, 1, 0]),
+        fi::RotateBack => Shuffle(3, vec![2, 0, 1]),
+        fi::Over => Shuffle(2, vec![0, 1, 0]),
+        fi::Nip => Shuffle(2, vec![1]),
+        fi::Tuck => Shuffle(2, vec![1, 0, 1]),
+        fi::TwoDup => Shuffle(2, vec![0, 1, 0, 1]),
+        fi::TwoDrop => Shuffle(2, vec![]),
+        fi::TwoSwap => Shuffle(4, vec![2, 3, 0, 1]),
+        _ => return None,
+    })
+}
+
+pub struct Problem {
+    pub position: Position,
+    pub message: String,
+}
+
+/// What is known about the words and variables a program can use.
+#[derive(Default)]
+pub struct Known {
+    words: HashMap<String, Effect>,
+    /// Words that never return, since every path through them throws or
+    /// exits.
+    endings: HashSet<String>,
+    /// The type of each variable, from every assignment to it.
+    variables: HashMap<String, Ty>,
+    /// How many places assign to each variable.
+    assignments: HashMap<String, usize>,
+    /// The types assigned to each variable in the places followed so far.
+    assigned: HashMap<String, Vec<Ty>>,
+    /// Variables whose type kept changing, so it is given up on.
+    unsettled: HashSet<String>,
+    /// Whether what is known about a word changed since the last `settle`.
+    learned: bool,
+}
+
+impl Known {
+    fn learn(&mut self, name: &str, module: Option<&str>, effect: Effect) {
+        self.learned |= self.words.get(name) != Some(&effect);
+        if let Some(module) = module {
+            self.words
+                .insert(format!("{module}.{name}"), effect.clone());
+        }
+        self.words.insert(name.to_string(), effect);
+    }
+
+    fn learn_ending(&mut self, name: &str, module: Option<&str>) {
+        self.learned |= self.endings.insert(name.to_string());
+        if let Some(module) = module {
+            self.endings.insert(format!("{module}.{name}"));
+        }
+    }
+
+    /// Learns the declared effects of words and where variables are assigned
+    /// to, before any word is checked, so that words can use ones defined
+    /// after them.
+    pub fn declare(&mut self, analysis: &Analysis, program: &[fi]) {
+        for definition in &analysis.definitions {
+            if let Some(effect) = definition.effect.as_deref().and_then(Effect::parse) {
+                self.learn(&definition.name, definition.module.as_deref(), effect);
+            }
+        }
+        for instr in program {
+            if let fi::Set(name) | fi::SetVar(name) = instr {
+                *self.assignments.entry(name.clone()).or_default() += 1;
+                self.variables.insert(name.clone(), Ty::Any);
+            }
+        }
+    }
+
+    /// Works out the types of variables from the assignments followed since
+    /// the last time. A variable keeps the type of every assignment to it if
+    /// they all agree and all of them could be followed. Returns whether
+    /// anything learned since the last time changed.
+    pub fn settle(&mut self) -> bool {
+        let mut changed = std::mem::take(&mut self.learned);
+        for (name, ty) in self.variables.iter_mut() {
+            let assigned = self
+                .assigned
+                .get(name)
+                .map(Vec::as_slice)
+                .unwrap_or_default();
+            let new = match assigned.split_first() {
+                Some((first, rest)) if assigned.len() == self.assignments[name] => {
+                    rest.iter().fold(*first, |a, b| a.join(*b))
+                }
+                _ => Ty::Any,
+            };
+            // A variable whose type changes twice is given up on, so that
+            // this settles.
+            if new == *ty || (*ty == Ty::Any && self.unsettled.contains(name)) {
+                continue;
+            }
+            changed = true;
+            *ty = if self.unsettled.insert(name.clone()) {
+                new
+            } else {
+                Ty::Any
+            };
+        }
+        self.assigned.clear();
+        changed
+    }
+
+    fn never_returns(&self, name: &str) -> bool {
+        self.endings.contains(name)
+            || name
+                .rsplit_once('.')
+                .is_some_and(|(_, n)| self.endings.contains(n))
+    }
+
+    /// Finds the effect of invoking a name, which may be prefixed with a
+    /// module or an alias of one.
+    fn invoke(&self, name: &str) -> Option<Effect> {
+        let bare = name
+            .rsplit_once('.')
+            .map(|(_, n)| n)
+            .filter(|n| !n.is_empty());
+        let variable = |n: &str| {
+            self.variables.get(n).map(|ty| Effect {
+                inputs: 0,
+                outputs: vec![*ty],
+            })
+        };
+        self.words
+            .get(name)
+            .or_else(|| bare.and_then(|n| self.words.get(n)))
+            .cloned()
+            .or_else(|| variable(name))
+            .or_else(|| bare.and_then(variable))
+    }
+}
+
+/// The checker gave up on a body, either because something in it has an
+/// effect that can't be known or because a problem was already reported.
+struct Stop;
+
+/// The stack along a path through a body.
+#[derive(Clone, PartialEq)]
+struct Stack {
+    /// How deep the stack is, relative to where the body starts.
+    depth: i64,
+    /// The types of the values on top, top-most last. The values under them
+    /// could be anything.
+    types: Vec<Ty>,
+}
+
+impl Stack {
+    /// The types of the `n` values on top, bottom-most first.
+    fn top(&self, n: usize) -> Vec<Ty> {
+        let mut types = vec![Ty::Any; n.saturating_sub(self.types.len())];
+        types.extend(&self.types[self.types.len().saturating_sub(n)..]);
+        types
+    }
+
+    /// The types of two stacks that continue from the same place, which
+    /// must be as deep.
+    fn join(&self, other: &Stack) -> Stack {
+        Stack {
+            depth: self.depth,
+            types: self
+                .types
+                .iter()
+                .rev()
+                .zip(other.types.iter().rev())
+                .map(|(a, b)| a.join(*b))
+                .collect::<Vec<Ty>>()
+                .into_iter()
+                .rev()
+                .collect(),
+        }
+    }
+}
+
+/// The stack along a path through a body, or `None` if the path can't get
+/// there.
+type Flow = Option<Stack>;
+
+/// Abstractly runs a body, keeping track of the stack.
+struct Checker<'a> {
+    program: &'a [fi],
+    positions: &'a [Position],
+    analysis: &'a Analysis,
+    known: &'a Known,
+    /// Whether to report values of the wrong type.
+    types: bool,
+    /// How far below its start the body may take the stack, if that is known.
+    floor: Option<i64>,
+    /// Says what gave the body its values, for underflow problems.
+    giver: String,
+    /// How far below its start the body has taken the stack.
+    lowest: i64,
+    /// The stacks at the `break`s of the loops being walked, innermost last.
+    breaks: Vec<Vec<Stack>>,
+    /// The variables assigned to, with the types of the values given.
+    assigned: Vec<(String, Ty)>,
+    problems: Vec<Problem>,
+}
+
+impl Checker<'_> {
+    fn report(&mut self, at: usize, message: String) -> Stop {
+        self.problems.push(Problem {
+            position: self.positions[at],
+            message,
+        });
+        Stop
+    }
+
+    /// Takes `n` values, returning their types bottom-most first.
+    fn take(&mut self, stack: &mut Stack, n: usize, at: usize) -> Result<Vec<Ty>, Stop> {
+        let depth = stack.depth - n as i64;
+        if let Some(floor) = self.floor
+            && depth < floor
+        {
+            let message = format!("This takes more values than {}", self.giver);
+            return Err(self.report(at, message));
+        }
+        self.lowest = self.lowest.min(depth);
+        stack.depth = depth;
+        let known = stack.types.len().min(n);
+        let mut taken = vec![Ty::Any; n - known];
+        taken.extend(stack.types.drain(stack.types.len() - known..));
+        Ok(taken)
+    }
+
+    fn apply(
+        &mut self,
+        path: &mut Flow,
+        needs: &[Need],
+        leaves: &[Ty],
+        at: usize,
+    ) -> Result<(), Stop> {
+        let Some(stack) = path else { return Ok(()) };
+        let taken = self.take(stack, needs.len(), at)?;
+        for (need, ty) in needs.iter().zip(&taken) {
+            if self.types && need.refuses(*ty) {
+                let word = match self.analysis.token_at(self.positions[at]) {
+                    Some(token) => format!("`{}`", token.raw),
+                    None => "This".to_string(),
+                };
+                self.problems.push(Problem {
+                    position: self.positions[at],
+                    message: format!("{word} needs {need} but would get {ty}"),
+                });
+            }
+        }
+        if let fi::Set(name) | fi::SetVar(name) = &self.program[at] {
+            self.assigned.push((name.clone(), taken[0]));
+        }
+        stack.depth += leaves.len() as i64;
+        stack.types.extend(leaves);
+        Ok(())
+    }
+
+    fn shuffle(
+        &mut self,
+        path: &mut Flow,
+        inputs: usize,
+        order: &[usize],
+        at: usize,
+    ) -> Result<(), Stop> {
+        let Some(stack) = path else { return Ok(()) };
+        let taken = self.take(stack, inputs, at)?;
+        stack.depth += order.len() as i64;
+        stack.types.extend(order.iter().map(|i| taken[*i]));
+        Ok(())
+    }
+
+    fn invoke(&mut self, path: &mut Flow, effect: &Effect, at: usize) -> Result<(), Stop> {
+        self.apply(path, &vec![Need::Any; effect.inputs], &effect.outputs, at)
+    }
+
+    /// Joins two paths that continue from the same place.
+    fn merge(&mut self, a: Flow, b: Flow, at: usize, what: &str) -> Result<Flow, Stop> {
+        match (a, b) {
+            (Some(a), Some(b)) if a.depth != b.depth => Err(self.report(
+                at,
+                format!(
+                    "The {what} differ in stack depth by {}",
+                    (a.depth - b.depth).abs()
+                ),
+            )),
+            (Some(a), Some(b)) => Ok(Some(a.join(&b))),
+            (Some(s), None) | (None, Some(s)) => Ok(Some(s)),
+            (None, None) => Ok(None),
+        }
+    }
+
+    /// Steps over the instruction at `at` if it is the one expected to close
+    /// a block.
+    fn close(&self, at: &mut usize, closer: fn(&fi) -> bool) -> Result<(), Stop> {
+        match self.program.get(*at) {
+            Some(instr) if closer(instr) => {
+                *at += 1;
+                Ok(())
+            }
+            _ => Err(Stop),
+        }
+    }
+
+    /// Walks a loop starting after its `Loop`, until the types at its start
+    /// stop changing, leaving `at` after its `LoopEnd`.
+    fn walk_loop(&mut self, at: &mut usize, mut path: Flow, start: usize) -> Result<Flow, Stop> {
+        let body = *at;
+        let reported = self.problems.len();
+        let assigned = self.assigned.len();
+        loop {
+            *at = body;
+            self.breaks.push(vec![]);
+            let end = self.walk(at, path.clone())?;
+            self.close(at, |i| matches!(i, fi::LoopEnd))?;
+            let breaks = self.breaks.pop().unwrap_or_default();
+            match (&path, &end) {
+                (Some(before), Some(after)) if before.depth != after.depth => {
+                    let message = format!(
+                        "This loop changes the stack depth by {:+} on each iteration",
+                        after.depth - before.depth
+                    );
+                    return Err(self.report(start, message));
+                }
+                // The next iteration starts with what this one left, so the
+                // body is walked again if that is less precise.
+                (Some(before), Some(after)) if before.join(after) != *before => {
+                    path = Some(before.join(after));
+                    self.problems.truncate(reported);
+                    self.assigned.truncate(assigned);
+                    continue;
+                }
+                _ => {}
+            }
+            let mut after = None;
+            for stack in breaks {
+                after = self.merge(after, Some(stack), start, "`break`s of this loop")?;
+            }
+            return Ok(after);
+        }
+    }
+
+    /// Walks from `at` to the end of the block it is in, leaving `at` on the
+    /// instruction that ends the block.
+    fn walk(&mut self, at: &mut usize, mut path: Flow) -> Result<Flow, Stop> {
+        while let Some(instr) = self.program.get(*at) {
+            let start = *at;
+            *at += 1;
+            match instr {
+                fi::Else | fi::IfEnd | fi::LoopEnd | fi::Catch | fi::EndTry | fi::EndWord => {
+                    *at = start;
+                    break;
+                }
+                fi::If => {
+                    self.shuffle(&mut path, 1, &[0], start)?;
+                    let then = self.walk(at, path.clone())?;
+                    let otherwise = match self.program.get(*at) {
+                        Some(fi::Else) => {
+                            *at += 1;
+                            self.walk(at, path)?
+                        }
+                        _ => path,
+                    };
+                    self.close(at, |i| matches!(i, fi::IfEnd))?;
+                    path = self.merge(then, otherwise, start, "branches of this condition")?;
+                }
+                fi::Loop => path = self.walk_loop(at, path, start)?,
+                fi::Break => {
+                    let Some(breaks) = self.breaks.last_mut() else {
+                        return Err(Stop);
+                    };
+                    breaks.extend(path.take());
+                }
+                // The next pair of a for loop is only pushed if there is one,
+                // so the check for the end of the table is taken along with it.
+                fi::ForEachNext => match self.program[*at..] {
+                    [fi::If, fi::Drop, fi::Break, fi::IfEnd, fi::Drop, ..] => {
+                        let Some(breaks) = self.breaks.last_mut() else {
+                            return Err(Stop);
+                        };
+                        breaks.extend(path.clone());
+                        self.apply(&mut path, &[], &[Ty::Any, Ty::Any], start)?;
+                        *at += 5;
+                    }
+                    _ => return Err(Stop),
+                },
+                fi::Try => {
+                    let body = self.walk(at, path.clone())?;
+                    self.close(at, |i| matches!(i, fi::Catch))?;
+                    // The stack is put back as deep as it was at the `try`,
+                    // with the error on top.
+                    let caught = path.map(|s| Stack {
+                        depth: s.depth + 1,
+                        types: vec![Ty::Any],
+                    });
+                    let caught = self.walk(at, caught)?;
+                    self.close(at, |i| matches!(i, fi::EndTry))?;
+                    path = self.merge(body, caught, start, "`try` and `catch` of this block")?;
+                }
+                // Words defined in a body are checked on their own.
+                fi::MakeWord(_) | fi::MakeWordVar(_) => {
+                    *at = definition_end(self.program, start);
+                }
+                fi::Throw => {
+                    self.apply(&mut path, &[Need::Any], &[], start)?;
+                    path = None;
+                }
+                fi::Exit => path = None,
+                fi::ExitWith => {
+                    self.apply(&mut path, &[INT], &[], start)?;
+                    path = None;
+                }
+                fi::Push(v) => self.apply(&mut path, &[], &[Ty::of(v)], start)?,
+                // `n pick` and `n roll` can only be checked when `n` is known.
+                fi::Pick | fi::Roll => match start.checked_sub(1).map(|i| &self.program[i]) {
+                    Some(fi::Push(ForestValue::Int(n))) if *n >= 0 => {
+                        let n = *n as usize;
+                        self.apply(&mut path, &[INT], &[], start)?;
+                        let order: Vec<usize> = match instr {
+                            fi::Pick => (0..=n).chain([0]).collect(),
+                            _ => (1..=n).chain([0]).collect(),
+                        };
+                        self.shuffle(&mut path, n + 1, &order, start)?;
+                    }
+                    _ => return Err(Stop),
+                },
+                fi::InvokeWord(name) if self.known.never_returns(name) => path = None,
+                fi::InvokeWord(name) => match self.known.invoke(name) {
+                    Some(effect) => self.invoke(&mut path, &effect, start)?,
+                    None => return Err(Stop),
+                },
+                instr => match action(instr) {
+                    Some(Action::Typed(needs, leaves)) => {
+                        self.apply(&mut path, needs, leaves, start)?
+                    }
+                    Some(Action::Shuffle(inputs, order)) => {
+                        self.shuffle(&mut path, inputs, &order, start)?
+                    }
+                    None => return Err(Stop),
+                },
+            }
+        }
+        Ok(path)
+    }
+}
+
+fn values(n: i64) -> String {
+    if n == 1 {
+        "1 value".to_string()
+    } else {
+        format!("{n} values")
+    }
+}
+
+/// Finds the instruction after the `EndWord` of the word defined at `start`.
+fn definition_end(program: &[fi], start: usize) -> usize {
+    let mut layers = 0;
+    for (i, instr) in program.iter().enumerate().skip(start) {
+        match instr {
+            fi::MakeWord(_) | fi::MakeWordVar(_) => layers += 1,
+            fi::EndWord => layers -= 1,
+            _ => {}
+        }
+        if layers == 0 {
+            return i + 1;
+        }
+    }
+    program.len()
+}
+
+/// Checks every word in a program, and the code outside of them, against
+/// what is known. Words without a declared effect have theirs inferred, for
+/// the words after them to use. Values of the wrong type are only reported
+/// with `types`.
+pub fn check_program(
+    program: &[fi],
+    positions: &[Position],
+    analysis: &Analysis,
+    known: &mut Known,
+    types: bool,
+) -> Vec<Problem> {
+    let mut problems = Vec::new();
+    for (start, instr) in program.iter().enumerate() {
+        let (fi::MakeWord(name) | fi::MakeWordVar(name)) = instr else {
+            continue;
+        };
+        // The name of a word comes right after the `:` it was compiled from.
+        let at = positions[start];
+        let definition = analysis.definitions.iter().find(|d| {
+            matches!(d.kind, DefinitionKind::Word | DefinitionKind::ConstantWord)
+                && d.name == *name
+                && (d.position.line, d.position.column) > (at.line, at.column)
+        });
+        // Words that take or leave a varying number of values are left alone.
+        if definition
+            .and_then(|d| d.effect.as_deref())
+            .is_some_and(|e| e.contains("..."))
+        {
+            continue;
+        }
+        let declared = definition.and_then(|d| Some((d, Effect::parse(d.effect.as_deref()?)?)));
+        let mut checker = Checker {
+            program,
+            positions,
+            analysis,
+            known,
+            types,
+            floor: declared.as_ref().map(|(_, e)| -(e.inputs as i64)),
+            giver: match &declared {
+                Some((d, e)) => format!(
+                    "the {} the stack effect `{}` gives `{name}`",
+                    e.inputs,
+                    d.effect.as_deref().unwrap_or_default()
+                ),
+                None => String::new(),
+            },
+            lowest: 0,
+            breaks: vec![],
+            assigned: vec![],
+            problems: vec![],
+        };
+        let mut body = start + 1;
+        let start_stack = Stack {
+            depth: 0,
+            types: vec![],
+        };
+        let end = checker.walk(&mut body, Some(start_stack));
+        let lowest = checker.lowest;
+        problems.append(&mut checker.problems);
+        for (variable, ty) in checker.assigned {
+            known.assigned.entry(variable).or_default().push(ty);
+        }
+        let end = match end {
+            Ok(Some(end)) => end,
+            Ok(None) if declared.is_none() => {
+                known.learn_ending(name, definition.and_then(|d| d.module.as_deref()));
+                continue;
+            }
+            _ => continue,
+        };
+        match declared {
+            Some((d, e)) if end.depth + e.inputs as i64 != e.outputs.len() as i64 => {
+                problems.push(Problem {
+                    position: d.position,
+                    message: format!(
+                        "`{name}` leaves {}, but its stack effect `{}` says {}",
+                        values(end.depth + e.inputs as i64),
+                        d.effect.as_deref().unwrap_or_default(),
+                        e.outputs.len()
+                    ),
+                })
+            }
+            // The types a word leaves are only known from its body.
+            Some((d, e)) => known.learn(
+                name,
+                d.module.as_deref(),
+                Effect {
+                    inputs: e.inputs,
+                    outputs: end.top(e.outputs.len()),
+                },
+            ),
+            None => known.learn(
+                name,
+                definition.and_then(|d| d.module.as_deref()),
+                Effect {
+                    inputs: -lowest as usize,
+                    outputs: end.top((end.depth - lowest) as usize),
+                },
+            ),
+        }
+    }
+    let mut checker = Checker {
+        program,
+        positions,
+        analysis,
+        known,
+        types,
+        floor: Some(0),
+        giver: "there are on the stack".to_string(),
+        lowest: 0,
+        breaks: vec![],
+        assigned: vec![],
+        problems: vec![],
+    };
+    let mut at = 0;
+    // Whatever the top level leaves on the stack is fine.
+    let _ = checker.walk(
+        &mut at,
+        Some(Stack {
+            depth: 0,
+            types: vec![],
+        }),
+    );
+    problems.append(&mut checker.problems);
+    for (variable, ty) in checker.assigned {
+        known.assigned.entry(variable).or_default().push(ty);
+    }
+    problems
+}
+
+/// Checks a file with what its includes and the prelude declare, printing
+/// the problems found. Fails if there are any, or if it doesn't compile.
+fn check_file(path: &Path, types: bool) -> Result<(), ()> {
+    let text = match fs::read_to_string(path) {
+        Ok(t) => t,
+        Err(e) => {
+            eprintln!("{}: could not read file, reason: {e}", path.display());
+            return Err(());
+        }
+    };
+    if let Err(e) = check(&text, Some(path)) {
+        eprintln!("{}: {e}", path.display());
+        return Err(());
+    }
+    // The file compiles, so it comes first, before what it includes.
+    let files = visible(path, |p| fs::read_to_string(p).ok());
+    let compiled: Vec<_> = files
+        .iter()
+        .filter_map(|file| {
+            let path = match &file.source {
+                Source::File(p) => Some(p.as_path()),
+                Source::Bundled => None,
+            };
+            check(&file.text, path).ok().map(|c| (file, c))
+        })
+        .collect();
+    let mut known = Known::default();
+    for (file, (program, _)) in &compiled {
+        known.declare(&file.analysis, program);
+    }
+    // Included files are checked first, so the file can use what is inferred
+    // about them, but only the problems of the file itself are reported.
+    // Variables get their types from every assignment to them, so everything
+    // is checked again until those stop changing.
+    let mut problems = Vec::new();
+    for _ in 0..MAX_PASSES {
+        for (file, (program, positions)) in compiled.iter().rev() {
+            problems = check_program(program, positions, &file.analysis, &mut known, types);
+        }
+        if !known.settle() {
+            break;
+        }
+    }
+    problems.sort_by_key(|p| (p.position.line, p.position.column));
+    for problem in &problems {
+        println!(
+            "{}:{}: {}",
+            path.display(),
+            problem.position,
+            problem.message
+        );
+    }
+    if problems.is_empty() { Ok(()) } else { Err(()) }
+}
+
+/// Checks the stack effects of every `.frt` file in `paths` (or the current
+/// directory). With `--types`, values of the wrong type are checked for too.
+pub fn run_check(args: &[String]) -> Result<(), ()> {
+    let types = args.iter().any(|a| a == "--types");
+    let mut files: Vec<PathBuf> = Vec::new();
+    let paths: Vec<&str> = args
+        .iter()
+        .filter(|a| *a != "--types")
+        .map(String::as_str)
+        .collect();
+    if paths.is_empty() {
+        collect_files(Path::new("."), &mut files);
+    } else {
+        paths
+            .iter()
+            .for_each(|p| collect_files(Path::new(p), &mut files));
+    }
+    let mut failed = false;
+    for file in files {
+        failed |= check_file(&file, types).is_err();
+    }
+    if failed { Err(()) } else { Ok(()) }
+}
@@ -1,14 +1,51 @@
 use crate::compile::ForestCompileError;
 use crate::compile::compile;
-use std::collections::HashMap;
+use crate::lexer::Position;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::env;
 use std::fmt;
-use std::fs::File;
-use std::io::Read;
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+/// Libraries compiled into the executable, includable by their logical name.
+const BUNDLED_LIBRARIES: &[(&str, &str)] = &[("std", include_str!("../libfrt/std.frt"))];
+
+/// Finds the source of a bundled library by its logical name.
+pub fn bundled_library(name: &str) -> Option<&'static str> {
+    BUNDLED_LIBRARIES
+        .iter()
+        .find(|(n, _)| *n == name)
+        .map(|(_, source)| *source)
+}
+
+/// Whether `source` is that of a bundled library, like libfrt/std.frt
+/// wherever the repository is.
+pub fn is_bundled_source(source: &str) -> bool {
+    BUNDLED_LIBRARIES.iter().any(|(_, s)| *s == source)
+}
+
+/// Finds the file `filename` refers to when included from `from`: next to
+/// `from` (or in the current directory without it), then in `FOREST_PATH`.
+pub fn resolve_include(from: Option<&Path>, filename: &str) -> Option<PathBuf> {
+    let mut candidates: Vec<PathBuf> = Vec::new();
+    match from.and_then(|p| p.parent()) {
+        Some(dir) => candidates.push(dir.join(filename)),
+        None => candidates.push(PathBuf::from(filename)),
+    }
+    if let Some(paths) = env::var_os("FOREST_PATH") {
+        env::split_paths(&paths).for_each(|dir| candidates.push(dir.join(filename)));
+    }
+    candidates
+        .into_iter()
+        .find(|p| p.is_file())
+        .map(|p| p.canonicalize().unwrap_or(p))
+}
 
 #[derive(PartialEq, Clone)]
 pub struct TablePair {
-    key: ForestValue,
-    value: ForestValue,
+    pub key: ForestValue,
+    pub value: ForestValue,
 }
 
 #[derive(Clone, PartialEq)]
@@ -26,7 +63,7 @@ impl fmt::Display for ForestValue {
             ForestValue::Int(i) => write!(f, "{}", i),
             ForestValue::String(s) => write!(f, "{:?}", s),
             ForestValue::Table(t) => {
-                if t.len() > 0 {
+                if !t.is_empty() {
                     let mut ret = String::new();
                     ret.push('{');
                     for TablePair { key, value } in t {
@@ -62,6 +99,7 @@ pub enum ForestInstruction {
     Values,
     If,
     IfEnd,
+    Else,
     And,
     Or,
     Not,
@@ -71,17 +109,72 @@ pub enum ForestInstruction {
     Loop,
     LoopEnd,
     Break,
+    LoopStep,
+    LoopIndex(usize),
+    ForEachNext,
     MakeWord(String),
     MakeWordVar(String),
     EndWord,
     InvokeWord(String),
     Swap,
     Rotate,
+    RotateBack,
+    Over,
+    Nip,
+    Tuck,
+    Pick,
+    Roll,
+    TwoDup,
+    TwoDrop,
+    TwoSwap,
+    DupIfTrue,
+    Depth,
+    ToReturn,
+    FromReturn,
+    FetchReturn,
     Splat,
     Set(String),
     SetVar(String),
+    Shadow(String),
+    Unshadow,
     Include,
+    EndInclude,
+    Eval,
+    NextToken,
+    Emit,
+    EmitToken,
+    Module(String),
+    EnterScope(Option<String>),
+    ExitScope,
+    Private,
+    Public,
+    Using(String),
+    Alias(String, String),
+    Assert,
+    AssertEq,
+    AssertStack,
+    ExpectError,
+    ReadFile,
+    ReadLines,
+    WriteFile,
+    AppendFile,
+    FileExists,
+    DeleteFile,
+    RenameFile,
+    ListDir,
+    MakeDir,
+    Args,
+    GetEnv,
+    SetEnv,
+    Cwd,
+    ScriptPath,
+    Try,
+    Catch,
+    EndTry,
+    Throw,
+    Input,
     Exit,
+    ExitWith,
 }
 
 impl fmt::Display for ForestInstruction {
@@ -103,6 +196,7 @@ impl fmt::Display for ForestInstruction {
             Self::Values => write!(f, "Values"),
             Self::If => write!(f, "If"),
             Self::IfEnd => write!(f, "IfEnd"),
+            Self::Else => write!(f, "Else"),
             Self::And => write!(f, "And"),
             Self::Or => write!(f, "Or"),
             Self::Not => write!(f, "Not"),
@@ -112,68 +206,273 @@ impl fmt::Display for ForestInstruction {
             Self::Loop => write!(f, "Loop"),
             Self::LoopEnd => write!(f, "LoopEnd"),
             Self::Break => write!(f, "Break"),
+            Self::LoopStep => write!(f, "LoopStep"),
+            Self::LoopIndex(n) => write!(f, "LoopIndex {}", n),
+            Self::ForEachNext => write!(f, "ForEachNext"),
             Self::MakeWord(w) => write!(f, "MakeWord {}", w),
             Self::MakeWordVar(w) => write!(f, "MakeWordVar {}", w),
             Self::EndWord => write!(f, "EndWord"),
             Self::InvokeWord(w) => write!(f, "InvokeWord {}", w),
             Self::Swap => write!(f, "Swap"),
             Self::Rotate => write!(f, "Rotate"),
+            Self::RotateBack => write!(f, "RotateBack"),
+            Self::Over => write!(f, "Over"),
+            Self::Nip => write!(f, "Nip"),
+            Self::Tuck => write!(f, "Tuck"),
+            Self::Pick => write!(f, "Pick"),
+            Self::Roll => write!(f, "Roll"),
+            Self::TwoDup => write!(f, "TwoDup"),
+            Self::TwoDrop => write!(f, "TwoDrop"),
+            Self::TwoSwap => write!(f, "TwoSwap"),
+            Self::DupIfTrue => write!(f, "DupIfTrue"),
+            Self::Depth => write!(f, "Depth"),
+            Self::ToReturn => write!(f, "ToReturn"),
+            Self::FromReturn => write!(f, "FromReturn"),
+            Self::FetchReturn => write!(f, "FetchReturn"),
             Self::Splat => write!(f, "Splat"),
             Self::Set(s) => write!(f, "Set {s}"),
             Self::SetVar(s) => write!(f, "SetVar {s}"),
+            Self::Shadow(s) => write!(f, "Shadow {s}"),
+            Self::Unshadow => write!(f, "Unshadow"),
             Self::Include => write!(f, "Include"),
+            Self::Eval => write!(f, "Eval"),
+            Self::NextToken => write!(f, "NextToken"),
+            Self::Emit => write!(f, "Emit"),
+            Self::EmitToken => write!(f, "EmitToken"),
+            Self::EndInclude => write!(f, "EndInclude"),
+            Self::Module(m) => write!(f, "Module {m}"),
+            Self::EnterScope(Some(m)) => write!(f, "EnterScope {m}"),
+            Self::EnterScope(None) => write!(f, "EnterScope <global>"),
+            Self::ExitScope => write!(f, "ExitScope"),
+            Self::Private => write!(f, "Private"),
+            Self::Public => write!(f, "Public"),
+            Self::Using(m) => write!(f, "Using {m}"),
+            Self::Alias(a, m) => write!(f, "Alias {a} {m}"),
+            Self::Assert => write!(f, "Assert"),
+            Self::AssertEq => write!(f, "AssertEq"),
+            Self::AssertStack => write!(f, "AssertStack"),
+            Self::ExpectError => write!(f, "ExpectError"),
+            Self::ReadFile => write!(f, "ReadFile"),
+            Self::ReadLines => write!(f, "ReadLines"),
+            Self::WriteFile => write!(f, "WriteFile"),
+            Self::AppendFile => write!(f, "AppendFile"),
+            Self::FileExists => write!(f, "FileExists"),
+            Self::DeleteFile => write!(f, "DeleteFile"),
+            Self::RenameFile => write!(f, "RenameFile"),
+            Self::ListDir => write!(f, "ListDir"),
+            Self::MakeDir => write!(f, "MakeDir"),
+            Self::Args => write!(f, "Args"),
+            Self::GetEnv => write!(f, "GetEnv"),
+            Self::SetEnv => write!(f, "SetEnv"),
+            Self::Cwd => write!(f, "Cwd"),
+            Self::ScriptPath => write!(f, "ScriptPath"),
+            Self::Try => write!(f, "Try"),
+            Self::Catch => write!(f, "Catch"),
+            Self::EndTry => write!(f, "EndTry"),
+            Self::Throw => write!(f, "Throw"),
+            Self::Input => write!(f, "Input"),
             Self::Exit => write!(f, "Exit"),
+            Self::ExitWith => write!(f, "ExitWith"),
         }
     }
 }
 
 #[allow(dead_code)]
 pub enum ForestError {
-    Halt,
+    Halt(i32),
     Underflow,
     TypeMismatch(ForestValue, ForestValue),
     UnbalancedIfEnd,
     UnbalancedLoopEnd,
-    EndedWithoutHalting,
     UnbalancedWordEnd,
     UseOfUndeclaredWord(String),
     Unimplemented(String),
     ReassigningConstant(String),
-    FileNotFound,
-    ErrorReadingFile,
+    FileNotFound(String),
+    ErrorReadingFile(String, String),
+    FileSystemError(String, String),
     IncludeCompileError(ForestCompileError),
+    EvalCompileError(ForestCompileError),
+    CyclicInclude(String),
+    PrivateWord(String),
+    OutsideModule(String),
+    AssertionFailed(String),
+    UnbalancedTry,
+    Thrown(ForestValue),
+    ReturnStackUnderflow,
+    NegativeIndex(i64),
+    CompileTimeOnly(String),
+    WaitingForInput,
+    WaitingForToken,
 }
 
 impl fmt::Display for ForestError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
-            Self::Halt => write!(f, "Halted!"),
+            Self::Halt(code) => write!(f, "Halted with status {code}!"),
             Self::Underflow => write!(f, "Underflow!"),
             Self::TypeMismatch(v, t) => write!(f, "Expceted: {t}, got {v}"),
             Self::UnbalancedIfEnd => write!(f, "Unbalanced IfEnd!"),
             Self::UnbalancedLoopEnd => write!(f, "Unbalanced LoopEnd!"),
-            Self::EndedWithoutHalting => write!(f, "Program ended without halting!"),
             Self::UnbalancedWordEnd => write!(f, "Unbalanced WordEnd!"),
             Self::UseOfUndeclaredWord(n) => write!(f, "Use of undeclared word `{n}`!"),
             Self::ReassigningConstant(n) => write!(f, "Reassigning to constant `{n}`!"),
             Self::Unimplemented(feature) => write!(f, "{feature} is not implemented yet!"),
-            Self::FileNotFound => write!(f, "File could not be found in the current directory!"),
-            Self::ErrorReadingFile => write!(f, "Error reading file!"),
+            Self::FileNotFound(p) => write!(f, "File `{p}` could not be found!"),
+            Self::ErrorReadingFile(p, reason) => write!(f, "Error reading `{p}`: {reason}"),
+            Self::FileSystemError(p, reason) => write!(f, "Error accessing `{p}`: {reason}"),
             Self::IncludeCompileError(e) => write!(f, "Error while including, reason:\n> {e}"),
+            Self::EvalCompileError(e) => write!(f, "Error while evaluating, reason:\n> {e}"),
+            Self::CyclicInclude(p) => write!(f, "Cyclic include of `{p}`!"),
+            Self::PrivateWord(n) => write!(f, "`{n}` is private to its module!"),
+            Self::OutsideModule(kw) => write!(f, "`{kw}` used outside of a module!"),
+            Self::AssertionFailed(reason) => write!(f, "Assertion failed: {reason}"),
+            Self::UnbalancedTry => write!(f, "Unbalanced Try!"),
+            Self::Thrown(v) => write!(f, "Uncaught exception: {v}"),
+            Self::ReturnStackUnderflow => write!(f, "Return stack underflow!"),
+            Self::NegativeIndex(i) => write!(f, "Expected a non-negative index, got {i}"),
+            Self::CompileTimeOnly(w) => {
+                write!(
+                    f,
+                    "`{w}` can only be used by immediate words while compiling!"
+                )
+            }
+            Self::WaitingForInput => write!(f, "Waiting for input!"),
+            Self::WaitingForToken => write!(f, "Waiting for a token!"),
         }
     }
 }
 
+/// Turns an error from the file system into a `ForestError` about `path`.
+fn file_error(path: &str, e: io::Error) -> ForestError {
+    match e.kind() {
+        io::ErrorKind::NotFound => ForestError::FileNotFound(path.to_string()),
+        io::ErrorKind::InvalidData => {
+            ForestError::ErrorReadingFile(path.to_string(), e.to_string())
+        }
+        _ => ForestError::FileSystemError(path.to_string(), e.to_string()),
+    }
+}
+
+impl ForestError {
+    /// The name of the error, as seen by `catch`.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Self::Halt(_) => "Halt",
+            Self::Underflow => "Underflow",
+            Self::TypeMismatch(_, _) => "TypeMismatch",
+            Self::UnbalancedIfEnd => "UnbalancedIfEnd",
+            Self::UnbalancedLoopEnd => "UnbalancedLoopEnd",
+            Self::UnbalancedWordEnd => "UnbalancedWordEnd",
+            Self::UseOfUndeclaredWord(_) => "UseOfUndeclaredWord",
+            Self::Unimplemented(_) => "Unimplemented",
+            Self::ReassigningConstant(_) => "ReassigningConstant",
+            Self::FileNotFound(_) => "FileNotFound",
+            Self::ErrorReadingFile(_, _) => "ErrorReadingFile",
+            Self::FileSystemError(_, _) => "FileSystemError",
+            Self::IncludeCompileError(_) => "IncludeCompileError",
+            Self::EvalCompileError(_) => "EvalCompileError",
+            Self::CyclicInclude(_) => "CyclicInclude",
+            Self::PrivateWord(_) => "PrivateWord",
+            Self::OutsideModule(_) => "OutsideModule",
+            Self::AssertionFailed(_) => "AssertionFailed",
+            Self::UnbalancedTry => "UnbalancedTry",
+            Self::Thrown(_) => "Thrown",
+            Self::ReturnStackUnderflow => "ReturnStackUnderflow",
+            Self::NegativeIndex(_) => "NegativeIndex",
+            Self::CompileTimeOnly(_) => "CompileTimeOnly",
+            Self::WaitingForInput => "WaitingForInput",
+            Self::WaitingForToken => "WaitingForToken",
+        }
+    }
+}
+
+#[derive(Clone)]
 pub struct Word {
     instructions: Vec<ForestInstruction>,
     is_constant: bool,
+    module: Option<String>,
+    is_private: bool,
+}
+
+/// A module being defined or executed in. Words defined while it is the
+/// innermost scope belong to its module.
+#[derive(Clone)]
+struct Scope {
+    module: Option<String>,
+    private: bool,
+}
+
+/// Where to go back to when an error happens inside a `try`, or inside the
+/// word run by `expect-error`. The stacks are copied whole, since the code
+/// that errored may have taken values off them.
+struct TryFrame {
+    catch_at: usize,
+    /// The word an `expect-error` expects to fail, if this frame is for one.
+    /// When it errors, no error value is pushed, and when it returns,
+    /// `expect-error` fails.
+    expecting: Option<String>,
+    stack: Vec<ForestValue>,
+    return_stack: Vec<ForestValue>,
+    jumplist_len: usize,
+    scopes_len: usize,
+    include_len: usize,
+    shadowed_len: usize,
 }
 
 pub struct ForestRuntime {
     stack: Vec<ForestValue>,
+    return_stack: Vec<ForestValue>,
     program: Vec<ForestInstruction>,
     jumplist: Vec<Vec<ForestInstruction>>,
     wordlist: HashMap<String, Word>,
+    input: VecDeque<ForestValue>,
+    include_stack: Vec<PathBuf>,
+    included: HashSet<PathBuf>,
+    scopes: Vec<Scope>,
+    search_order: Vec<String>,
+    aliases: HashMap<String, String>,
+    try_frames: Vec<TryFrame>,
+    /// Words hidden by the variables of for loops, innermost last, with the
+    /// names they were defined under, to be put back when the loops end.
+    shadowed: Vec<(String, Option<Word>)>,
+    args: Vec<String>,
+    tokens: VecDeque<ForestValue>,
+    /// What immediate words have emitted, if this runtime runs them.
+    emitted: Option<Vec<Emitted>>,
+    source_map: Option<SourceMap>,
+}
+
+/// Where the instructions the runtime was made with came from, to tell where
+/// an error happened.
+struct SourceMap {
+    source: String,
+    /// In the order of the program, so from the last instruction.
+    positions: Vec<Position>,
+    /// How many instructions at the bottom of the program are still the
+    /// ones the runtime was made with.
+    unrun: usize,
+    /// Where the last of them to run came from. Errors in words and included
+    /// files are reported where they were called from.
+    last: Option<Position>,
+}
+
+/// Code emitted by an immediate word, for the compiler to put in its place.
+pub enum Emitted {
+    /// Source code, compiled as if it came next in the file.
+    Source(String),
+    /// A value to push.
+    Value(ForestValue),
+}
+
+/// What a runtime is doing after being driven by `run_steps` or `run_until`.
+/// The runtime keeps its state in every case, so the host can resume it later.
+pub enum ForestStatus {
+    Running,
+    Halted(i32),
+    Errored(ForestError),
+    WaitingForInput,
+    WaitingForToken,
 }
 
 #[allow(dead_code)]
@@ -187,19 +486,409 @@ impl ForestRuntime {
         revprogram.reverse();
         ForestRuntime {
             stack: vec![],
+            return_stack: vec![],
             program: revprogram,
             jumplist: vec![],
             wordlist: HashMap::new(),
+            input: VecDeque::new(),
+            include_stack: vec![],
+            included: HashSet::new(),
+            scopes: vec![],
+            search_order: vec![],
+            aliases: HashMap::new(),
+            try_frames: vec![],
+            shadowed: vec![],
+            args: vec![],
+            tokens: VecDeque::new(),
+            emitted: None,
+            source_map: None,
+        }
+    }
+
+    /// Makes an empty runtime for running immediate words, which can read
+    /// tokens and emit code.
+    pub fn new_compile_time() -> Self {
+        let mut runtime = ForestRuntime::new(&[]);
+        runtime.emitted = Some(vec![]);
+        runtime
+    }
+
+    fn current_module(&self) -> Option<String> {
+        self.scopes.last().and_then(|s| s.module.clone())
+    }
+
+    /// Splits `m.word` into its module (with aliases applied) and word.
+    fn split_qualified<'a>(&'a self, name: &'a str) -> Option<(&'a str, &'a str)> {
+        match name.rsplit_once('.') {
+            Some((prefix, rest)) if !prefix.is_empty() && !rest.is_empty() => Some((
+                self.aliases
+                    .get(prefix)
+                    .map(String::as_str)
+                    .unwrap_or(prefix),
+                rest,
+            )),
+            _ => None,
         }
     }
 
-    pub fn push_instrs(&mut self, instrs: &[ForestInstruction]) -> () {
+    /// Finds the wordlist entry a name refers to. Qualified names are looked up
+    /// directly, other names in the current module, then in the modules added
+    /// with `using` (latest first), then globally.
+    fn resolve_word(&self, name: &str) -> Result<String, ForestError> {
+        let current = self.current_module();
+        if let Some((module, rest)) = self.split_qualified(name) {
+            let qualified = format!("{module}.{rest}");
+            return match self.wordlist.get(&qualified) {
+                Some(w) if w.is_private && w.module != current => {
+                    Err(ForestError::PrivateWord(name.to_string()))
+                }
+                Some(_) => Ok(qualified),
+                None => Err(ForestError::UseOfUndeclaredWord(name.to_string())),
+            };
+        }
+        if let Some(m) = current {
+            let qualified = format!("{m}.{name}");
+            if self.wordlist.contains_key(&qualified) {
+                return Ok(qualified);
+            }
+        }
+        for m in self.search_order.iter().rev() {
+            let qualified = format!("{m}.{name}");
+            if let Some(w) = self.wordlist.get(&qualified)
+                && !w.is_private
+            {
+                return Ok(qualified);
+            }
+        }
+        if self.wordlist.contains_key(name) {
+            Ok(name.to_string())
+        } else {
+            Err(ForestError::UseOfUndeclaredWord(name.to_string()))
+        }
+    }
+
+    /// Adds a word to the wordlist, in the current module unless the name is
+    /// qualified. Constants and other modules' private words can't be replaced.
+    fn define(
+        &mut self,
+        name: String,
+        instructions: Vec<ForestInstruction>,
+        is_constant: bool,
+    ) -> Result<(), ForestError> {
+        let current = self.current_module();
+        let (qualified, module, is_private) = self.qualify(&name);
+        if let Some(v) = self.wordlist.get(&qualified) {
+            if v.is_private && v.module != current {
+                return Err(ForestError::PrivateWord(name));
+            }
+            if v.is_constant {
+                return Err(ForestError::ReassigningConstant(name));
+            }
+        }
+        self.wordlist.insert(
+            qualified,
+            Word {
+                instructions,
+                is_constant,
+                module,
+                is_private,
+            },
+        );
+        Ok(())
+    }
+
+    /// The name a word defined as `name` here is stored under, with its
+    /// module and whether it is private.
+    fn qualify(&self, name: &str) -> (String, Option<String>, bool) {
+        let current = self.current_module();
+        match (self.split_qualified(name), &current) {
+            (Some((module, rest)), _) => {
+                (format!("{module}.{rest}"), Some(module.to_string()), false)
+            }
+            (None, Some(m)) => (
+                format!("{m}.{name}"),
+                current.clone(),
+                self.scopes.last().is_some_and(|s| s.private),
+            ),
+            (None, None) => (name.to_string(), None, false),
+        }
+    }
+
+    /// Puts back the words hidden by for loops, until only `len` are left.
+    fn unshadow(&mut self, len: usize) {
+        while self.shadowed.len() > len {
+            match self.shadowed.pop() {
+                Some((name, Some(word))) => self.wordlist.insert(name, word),
+                Some((name, None)) => self.wordlist.remove(&name),
+                None => None,
+            };
+        }
+    }
+
+    /// Records the file the program was read from, so that its includes are
+    /// resolved relative to it.
+    pub fn set_source_path(&mut self, path: &Path) {
+        let path = path.canonicalize().unwrap_or(path.to_path_buf());
+        self.included.insert(path.clone());
+        self.include_stack = vec![path];
+    }
+
+    /// Records where each instruction the runtime was made with came from in
+    /// `source`, for `location` to tell where an error happened.
+    pub fn set_source_map(&mut self, source: &str, positions: &[Position]) {
+        self.source_map = Some(SourceMap {
+            source: source.to_string(),
+            positions: positions.iter().rev().copied().collect(),
+            unrun: self.program.len(),
+            last: None,
+        });
+    }
+
+    /// Where in its source the last instruction of the program the runtime
+    /// was made with to run came from, like `main.frt:3:5`.
+    pub fn location(&self) -> Option<String> {
+        let map = self.source_map.as_ref()?;
+        Some(format!("{}:{}", map.source, map.last?))
+    }
+
+    /// Sets the arguments the program sees through `args`.
+    pub fn set_args(&mut self, args: Vec<String>) {
+        self.args = args;
+    }
+
+    /// Loads the bundled standard library before the rest of the program.
+    pub fn load_prelude(&mut self) {
+        self.push_instrs(&[
+            ForestInstruction::Push(ForestValue::String("std".to_string())),
+            ForestInstruction::Include,
+        ]);
+    }
+
+    /// Finds the path of the file an include refers to, which is `<name>` for
+    /// bundled libraries.
+    fn find_include(&self, filename: &str) -> Result<PathBuf, ForestError> {
+        if bundled_library(filename).is_some() {
+            return Ok(PathBuf::from(format!("<{filename}>")));
+        }
+        resolve_include(self.include_stack.last().map(PathBuf::as_path), filename)
+            .ok_or_else(|| ForestError::FileNotFound(filename.to_string()))
+    }
+
+    fn read_include(&self, path: &Path) -> Result<String, ForestError> {
+        if let Some((_, source)) = BUNDLED_LIBRARIES
+            .iter()
+            .find(|(name, _)| path == Path::new(&format!("<{name}>")))
+        {
+            return Ok(source.to_string());
+        }
+        fs::read_to_string(path).map_err(|e| file_error(&path.display().to_string(), e))
+    }
+
+    #[allow(dead_code)]
+    pub fn stack(&self) -> &[ForestValue] {
+        &self.stack
+    }
+
+    pub fn next_instruction(&self) -> Option<&ForestInstruction> {
+        self.program.last()
+    }
+
+    /// Hands a line of input to the runtime, to be picked up by `input`.
+    /// `None` signals the end of input, which `input` sees as nil.
+    pub fn provide_input(&mut self, line: Option<&str>) {
+        self.input.push_back(match line {
+            Some(l) => ForestValue::String(l.to_string()),
+            None => ForestValue::Nil,
+        });
+    }
+
+    /// Hands the next token of the file being compiled to the runtime, to be
+    /// picked up by `next-token`. `None` signals the end of the file.
+    pub fn provide_token(&mut self, token: Option<&str>) {
+        self.tokens.push_back(match token {
+            Some(t) => ForestValue::String(t.to_string()),
+            None => ForestValue::Nil,
+        });
+    }
+
+    /// Takes everything emitted by immediate words since the last call.
+    pub fn take_emitted(&mut self) -> Vec<Emitted> {
+        self.emitted
+            .as_mut()
+            .map(std::mem::take)
+            .unwrap_or_default()
+    }
+
+    /// Runs at most `n` instructions.
+    pub fn run_steps(&mut self, n: usize) -> ForestStatus {
+        for _ in 0..n {
+            if let Err(e) = self.step() {
+                return Self::status_of(e);
+            }
+        }
+        ForestStatus::Running
+    }
+
+    /// Runs until `predicate` holds before the next instruction, or until the
+    /// program stops on its own.
+    pub fn run_until<F: FnMut(&ForestRuntime) -> bool>(
+        &mut self,
+        mut predicate: F,
+    ) -> ForestStatus {
+        while !predicate(self) {
+            if let Err(e) = self.step() {
+                return Self::status_of(e);
+            }
+        }
+        ForestStatus::Running
+    }
+
+    fn status_of(e: ForestError) -> ForestStatus {
+        match e {
+            ForestError::Halt(code) => ForestStatus::Halted(code),
+            ForestError::WaitingForInput => ForestStatus::WaitingForInput,
+            ForestError::WaitingForToken => ForestStatus::WaitingForToken,
+            e => ForestStatus::Errored(e),
+        }
+    }
+
+    pub fn push_instrs(&mut self, instrs: &[ForestInstruction]) {
         let mut revprogram: Vec<ForestInstruction> = instrs.to_vec();
         revprogram.reverse();
         self.program.append(&mut revprogram);
     }
 
+    /// A frame that goes back to the state of the runtime now, with the
+    /// program truncated to `catch_at`, when an error happens.
+    fn try_frame(&self, catch_at: usize, expecting: Option<String>) -> TryFrame {
+        TryFrame {
+            catch_at,
+            expecting,
+            stack: self.stack.clone(),
+            return_stack: self.return_stack.clone(),
+            jumplist_len: self.jumplist.len(),
+            scopes_len: self.scopes.len(),
+            include_len: self.include_stack.len(),
+            shadowed_len: self.shadowed.len(),
+        }
+    }
+
+    /// Fails an `expect-error` whose word returned without an error.
+    fn check_expectation(&mut self) -> Result<(), ForestError> {
+        match self.try_frames.last() {
+            Some(frame) if frame.expecting.is_some() && self.program.len() <= frame.catch_at => {
+                let word = self.try_frames.pop().and_then(|f| f.expecting);
+                Err(ForestError::AssertionFailed(format!(
+                    "`{}` did not error",
+                    word.unwrap_or_default()
+                )))
+            }
+            _ => Ok(()),
+        }
+    }
+
     pub fn step(&mut self) -> Result<(), ForestError> {
+        if let Some(map) = &mut self.source_map {
+            // Jumps and caught errors can take instructions off below the
+            // ones pushed on top of the program.
+            map.unrun = map.unrun.min(self.program.len());
+            if map.unrun > 0 && map.unrun == self.program.len() {
+                map.unrun -= 1;
+                map.last = map.positions.get(map.unrun).copied();
+            }
+        }
+        match self.check_expectation().and_then(|_| self.step_uncaught()) {
+            Err(ForestError::Halt(code)) => Err(ForestError::Halt(code)),
+            Err(ForestError::WaitingForInput) => Err(ForestError::WaitingForInput),
+            Err(ForestError::WaitingForToken) => Err(ForestError::WaitingForToken),
+            Err(e) => {
+                if let Some(frame) = self.try_frames.pop() {
+                    let value = self.error_value(e);
+                    self.program.truncate(frame.catch_at);
+                    self.stack = frame.stack;
+                    self.return_stack = frame.return_stack;
+                    self.jumplist.truncate(frame.jumplist_len);
+                    self.scopes.truncate(frame.scopes_len);
+                    self.include_stack.truncate(frame.include_len);
+                    self.unshadow(frame.shadowed_len);
+                    if frame.expecting.is_none() {
+                        self.stack.push(value);
+                    }
+                    return Ok(());
+                }
+                Err(e)
+            }
+            Ok(()) => Ok(()),
+        }
+    }
+
+    /// Turns an error into the value `catch` pushes: thrown values as they
+    /// are, and built-in errors as a table of their kind, message and location.
+    fn error_value(&self, e: ForestError) -> ForestValue {
+        if let ForestError::Thrown(v) = e {
+            return v;
+        }
+        // Included files have no source map, so only their path is known.
+        let location = match (self.include_stack.as_slice(), self.location()) {
+            ([] | [_], Some(location)) => location,
+            ([.., p], _) => p.display().to_string(),
+            ([], None) => "<unknown>".to_string(),
+        };
+        let string = |s: &str| ForestValue::String(s.to_string());
+        ForestValue::Table(vec![
+            TablePair {
+                key: string("kind"),
+                value: string(e.kind()),
+            },
+            TablePair {
+                key: string("message"),
+                value: ForestValue::String(e.to_string()),
+            },
+            TablePair {
+                key: string("location"),
+                value: ForestValue::String(location),
+            },
+        ])
+    }
+
+    /// Replaces the top `n` values with the ones at the indices in `order`,
+    /// where 0 is the deepest of the `n`.
+    fn shuffle(&mut self, n: usize, order: &[usize]) -> Result<(), ForestError> {
+        if self.stack.len() < n {
+            return Err(ForestError::Underflow);
+        }
+        let top = self.stack.split_off(self.stack.len() - n);
+        order.iter().for_each(|i| self.stack.push(top[*i].clone()));
+        Ok(())
+    }
+
+    /// Pops a stack index for `pick` and `roll`, and turns it into an index
+    /// into the stack underneath it.
+    fn pop_depth_index(&mut self) -> Result<usize, ForestError> {
+        let depth = match self.stack.pop() {
+            Some(ForestValue::Int(i)) if i < 0 => return Err(ForestError::NegativeIndex(i)),
+            Some(ForestValue::Int(i)) => i as usize,
+            Some(v) => return Err(ForestError::TypeMismatch(v, ForestValue::Int(0))),
+            None => return Err(ForestError::Underflow),
+        };
+        if depth >= self.stack.len() {
+            return Err(ForestError::Underflow);
+        }
+        Ok(self.stack.len() - 1 - depth)
+    }
+
+    fn pop_string(&mut self) -> Result<String, ForestError> {
+        match self.stack.pop() {
+            Some(ForestValue::String(s)) => Ok(s),
+            Some(v) => Err(ForestError::TypeMismatch(
+                v,
+                ForestValue::String("".to_string()),
+            )),
+            None => Err(ForestError::Underflow),
+        }
+    }
+
+    fn step_uncaught(&mut self) -> Result<(), ForestError> {
         if let Some(inst) = self.program.pop() {
             match inst {
                 ForestInstruction::Push(v) => {
@@ -207,7 +896,7 @@ impl ForestRuntime {
                     Ok(())
                 }
                 ForestInstruction::Duplicate => {
-                    if self.stack.len() < 1 {
+                    if self.stack.is_empty() {
                         Err(ForestError::Underflow)
                     } else {
                         self.stack.push(self.stack[self.stack.len() - 1].clone());
@@ -291,7 +980,7 @@ impl ForestRuntime {
                     }
                 }
                 ForestInstruction::Stringify => {
-                    if self.stack.len() < 1 {
+                    if self.stack.is_empty() {
                         Err(ForestError::Underflow)
                     } else {
                         let a = self.stack.pop().unwrap();
@@ -325,13 +1014,20 @@ impl ForestRuntime {
                     }
                 }
                 ForestInstruction::Print => {
-                    if self.stack.len() < 1 {
+                    if self.stack.is_empty() {
                         Err(ForestError::Underflow)
                     } else {
                         let a = self.stack.pop().unwrap();
-                        match a {
-                            ForestValue::String(s) => print!("{s}"),
-                            v => print!("{v}"),
+                        let text = match a {
+                            ForestValue::String(s) => s,
+                            v => v.to_string(),
+                        };
+                        // Output while compiling isn't part of the program's
+                        // output, so it goes to stderr.
+                        if self.emitted.is_some() {
+                            eprint!("{text}");
+                        } else {
+                            print!("{text}");
                         }
                         Ok(())
                     }
@@ -402,7 +1098,7 @@ impl ForestRuntime {
                     }
                 }
                 ForestInstruction::Keys => {
-                    if self.stack.len() < 1 {
+                    if self.stack.is_empty() {
                         Err(ForestError::Underflow)
                     } else {
                         let table = self.stack.pop().unwrap();
@@ -423,7 +1119,7 @@ impl ForestRuntime {
                     }
                 }
                 ForestInstruction::Values => {
-                    if self.stack.len() < 1 {
+                    if self.stack.is_empty() {
                         Err(ForestError::Underflow)
                     } else {
                         let table = self.stack.pop().unwrap();
@@ -472,7 +1168,7 @@ impl ForestRuntime {
                     }
                 }
                 ForestInstruction::Not => {
-                    if self.stack.len() < 1 {
+                    if self.stack.is_empty() {
                         Err(ForestError::Underflow)
                     } else {
                         let a = self.stack.pop().unwrap();
@@ -553,6 +1249,57 @@ impl ForestRuntime {
                         Ok(())
                     }
                 }
+                ForestInstruction::RotateBack => self.shuffle(3, &[2, 0, 1]),
+                ForestInstruction::Over => self.shuffle(2, &[0, 1, 0]),
+                ForestInstruction::Nip => self.shuffle(2, &[1]),
+                ForestInstruction::Tuck => self.shuffle(2, &[1, 0, 1]),
+                ForestInstruction::TwoDup => self.shuffle(2, &[0, 1, 0, 1]),
+                ForestInstruction::TwoDrop => self.shuffle(2, &[]),
+                ForestInstruction::TwoSwap => self.shuffle(4, &[2, 3, 0, 1]),
+                ForestInstruction::Pick => {
+                    let idx = self.pop_depth_index()?;
+                    self.stack.push(self.stack[idx].clone());
+                    Ok(())
+                }
+                ForestInstruction::Roll => {
+                    let idx = self.pop_depth_index()?;
+                    let v = self.stack.remove(idx);
+                    self.stack.push(v);
+                    Ok(())
+                }
+                ForestInstruction::DupIfTrue => match self.stack.last() {
+                    Some(ForestValue::Nil) => Ok(()),
+                    Some(v) => {
+                        self.stack.push(v.clone());
+                        Ok(())
+                    }
+                    None => Err(ForestError::Underflow),
+                },
+                ForestInstruction::Depth => {
+                    self.stack.push(ForestValue::Int(self.stack.len() as i64));
+                    Ok(())
+                }
+                ForestInstruction::ToReturn => match self.stack.pop() {
+                    Some(v) => {
+                        self.return_stack.push(v);
+                        Ok(())
+                    }
+                    None => Err(ForestError::Underflow),
+                },
+                ForestInstruction::FromReturn => match self.return_stack.pop() {
+                    Some(v) => {
+                        self.stack.push(v);
+                        Ok(())
+                    }
+                    None => Err(ForestError::ReturnStackUnderflow),
+                },
+                ForestInstruction::FetchReturn => match self.return_stack.last() {
+                    Some(v) => {
+                        self.stack.push(v.clone());
+                        Ok(())
+                    }
+                    None => Err(ForestError::ReturnStackUnderflow),
+                },
                 ForestInstruction::Rotate => {
                     if self.stack.len() < 3 {
                         Err(ForestError::Underflow)
@@ -567,7 +1314,7 @@ impl ForestRuntime {
                     }
                 }
                 ForestInstruction::Splat => {
-                    if self.stack.len() < 1 {
+                    if self.stack.is_empty() {
                         Err(ForestError::Underflow)
                     } else {
                         let a = self.stack.pop().unwrap();
@@ -576,14 +1323,260 @@ impl ForestRuntime {
                                 .map(|p| p.value.clone())
                                 .rev()
                                 .for_each(|p| self.stack.push(p));
-                            return Ok(());
+                            Ok(())
                         } else {
-                            return Err(ForestError::TypeMismatch(ForestValue::Table(vec![]), a));
+                            Err(ForestError::TypeMismatch(ForestValue::Table(vec![]), a))
                         }
                     }
                 }
-                ForestInstruction::Exit => Err(ForestError::Halt),
+                ForestInstruction::Exit => Err(ForestError::Halt(0)),
+                ForestInstruction::ExitWith => match self.stack.pop() {
+                    Some(ForestValue::Int(code)) => Err(ForestError::Halt(code as i32)),
+                    Some(v) => Err(ForestError::TypeMismatch(v, ForestValue::Int(0))),
+                    None => Err(ForestError::Underflow),
+                },
+                ForestInstruction::ReadFile => {
+                    let path = self.pop_string()?;
+                    let contents = fs::read_to_string(&path).map_err(|e| file_error(&path, e))?;
+                    self.stack.push(ForestValue::String(contents));
+                    Ok(())
+                }
+                ForestInstruction::ReadLines => {
+                    let path = self.pop_string()?;
+                    let contents = fs::read_to_string(&path).map_err(|e| file_error(&path, e))?;
+                    self.stack.push(ForestValue::Table(
+                        contents
+                            .lines()
+                            .enumerate()
+                            .map(|(i, l)| TablePair {
+                                key: ForestValue::Int(i as i64),
+                                value: ForestValue::String(l.to_string()),
+                            })
+                            .collect(),
+                    ));
+                    Ok(())
+                }
+                ForestInstruction::WriteFile | ForestInstruction::AppendFile => {
+                    if self.stack.len() < 2 {
+                        return Err(ForestError::Underflow);
+                    }
+                    let contents = self.pop_string()?;
+                    let path = self.pop_string()?;
+                    let append = matches!(inst, ForestInstruction::AppendFile);
+                    OpenOptions::new()
+                        .create(true)
+                        .write(true)
+                        .append(append)
+                        .truncate(!append)
+                        .open(&path)
+                        .and_then(|mut f| f.write_all(contents.as_bytes()))
+                        .map_err(|e| file_error(&path, e))
+                }
+                ForestInstruction::FileExists => {
+                    let path = self.pop_string()?;
+                    self.stack.push(if fs::exists(&path).unwrap_or(false) {
+                        ForestValue::Int(1)
+                    } else {
+                        ForestValue::Nil
+                    });
+                    Ok(())
+                }
+                ForestInstruction::DeleteFile => {
+                    let path = self.pop_string()?;
+                    fs::remove_file(&path).map_err(|e| file_error(&path, e))
+                }
+                ForestInstruction::RenameFile => {
+                    if self.stack.len() < 2 {
+                        return Err(ForestError::Underflow);
+                    }
+                    let to = self.pop_string()?;
+                    let from = self.pop_string()?;
+                    fs::rename(&from, &to).map_err(|e| file_error(&from, e))
+                }
+                ForestInstruction::ListDir => {
+                    let path = self.pop_string()?;
+                    let mut names: Vec<String> = fs::read_dir(&path)
+                        .and_then(|rd| {
+                            rd.map(|e| e.map(|e| e.file_name().to_string_lossy().to_string()))
+                                .collect()
+                        })
+                        .map_err(|e| file_error(&path, e))?;
+                    names.sort();
+                    self.stack.push(ForestValue::Table(
+                        names
+                            .into_iter()
+                            .enumerate()
+                            .map(|(i, n)| TablePair {
+                                key: ForestValue::Int(i as i64),
+                                value: ForestValue::String(n),
+                            })
+                            .collect(),
+                    ));
+                    Ok(())
+                }
+                ForestInstruction::MakeDir => {
+                    let path = self.pop_string()?;
+                    fs::create_dir_all(&path).map_err(|e| file_error(&path, e))
+                }
+                ForestInstruction::Args => {
+                    self.stack.push(ForestValue::Table(
+                        self.args
+                            .iter()
+                            .enumerate()
+                            .map(|(i, a)| TablePair {
+                                key: ForestValue::Int(i as i64),
+                                value: ForestValue::String(a.clone()),
+                            })
+                            .collect(),
+                    ));
+                    Ok(())
+                }
+                ForestInstruction::GetEnv => {
+                    let name = self.pop_string()?;
+                    self.stack.push(match env::var(&name) {
+                        Ok(v) => ForestValue::String(v),
+                        Err(_) => ForestValue::Nil,
+                    });
+                    Ok(())
+                }
+                ForestInstruction::SetEnv => {
+                    if self.stack.len() < 2 {
+                        return Err(ForestError::Underflow);
+                    }
+                    let value = self.pop_string()?;
+                    let name = self.pop_string()?;
+                    // SAFETY: Forest programs run on a single thread, so nothing
+                    // can be reading the environment at the same time.
+                    unsafe { env::set_var(name, value) };
+                    Ok(())
+                }
+                ForestInstruction::Cwd => {
+                    let cwd = env::current_dir().map_err(|e| {
+                        ForestError::FileSystemError(".".to_string(), e.to_string())
+                    })?;
+                    self.stack
+                        .push(ForestValue::String(cwd.display().to_string()));
+                    Ok(())
+                }
+                ForestInstruction::ScriptPath => {
+                    self.stack.push(match self.include_stack.first() {
+                        Some(p) => ForestValue::String(p.display().to_string()),
+                        None => ForestValue::Nil,
+                    });
+                    Ok(())
+                }
+                ForestInstruction::Try => {
+                    let mut layers = 1;
+                    let mut catch_at = None;
+                    for (idx, inst) in self.program.iter().enumerate().rev() {
+                        match inst {
+                            ForestInstruction::Try => layers += 1,
+                            ForestInstruction::EndTry => layers -= 1,
+                            ForestInstruction::Catch if layers == 1 => {
+                                catch_at = Some(idx);
+                                break;
+                            }
+                            _ => {}
+                        }
+                    }
+                    match catch_at {
+                        Some(catch_at) => {
+                            self.try_frames.push(self.try_frame(catch_at, None));
+                            Ok(())
+                        }
+                        None => Err(ForestError::UnbalancedTry),
+                    }
+                }
+                ForestInstruction::Catch => {
+                    self.try_frames.pop();
+                    let mut layers = 1;
+                    while layers != 0 {
+                        match self.program.pop() {
+                            Some(ForestInstruction::Try) => layers += 1,
+                            Some(ForestInstruction::EndTry) => layers -= 1,
+                            Some(_) => {}
+                            None => return Err(ForestError::UnbalancedTry),
+                        }
+                    }
+                    Ok(())
+                }
+                ForestInstruction::EndTry => Ok(()),
+                ForestInstruction::Throw => match self.stack.pop() {
+                    Some(v) => Err(ForestError::Thrown(v)),
+                    None => Err(ForestError::Underflow),
+                },
+                ForestInstruction::Assert => match self.stack.pop() {
+                    Some(ForestValue::Nil) => {
+                        Err(ForestError::AssertionFailed("value was nil".to_string()))
+                    }
+                    Some(_) => Ok(()),
+                    None => Err(ForestError::Underflow),
+                },
+                ForestInstruction::AssertEq => {
+                    if self.stack.len() < 2 {
+                        Err(ForestError::Underflow)
+                    } else {
+                        let expected = self.stack.pop().unwrap();
+                        let actual = self.stack.pop().unwrap();
+                        if actual == expected {
+                            Ok(())
+                        } else {
+                            Err(ForestError::AssertionFailed(format!(
+                                "expected {expected}, got {actual}"
+                            )))
+                        }
+                    }
+                }
+                ForestInstruction::AssertStack => match self.stack.pop() {
+                    Some(ForestValue::Int(n)) => {
+                        if self.stack.len() as i64 == n {
+                            Ok(())
+                        } else {
+                            Err(ForestError::AssertionFailed(format!(
+                                "expected {n} values on the stack, got {}",
+                                self.stack.len()
+                            )))
+                        }
+                    }
+                    Some(v) => Err(ForestError::TypeMismatch(v, ForestValue::Int(0))),
+                    None => Err(ForestError::Underflow),
+                },
+                ForestInstruction::ExpectError => {
+                    let name = match self.stack.pop() {
+                        Some(ForestValue::String(n)) => n,
+                        Some(v) => {
+                            return Err(ForestError::TypeMismatch(
+                                v,
+                                ForestValue::String("".to_string()),
+                            ));
+                        }
+                        None => return Err(ForestError::Underflow),
+                    };
+                    // A word that doesn't exist is a mistake in the test, not
+                    // the error it expects.
+                    self.resolve_word(&name)?;
+                    // The word runs in place, so it sees everything it would
+                    // see if it was run directly, and an error in it goes back
+                    // to here.
+                    self.try_frames
+                        .push(self.try_frame(self.program.len(), Some(name.clone())));
+                    self.program.push(ForestInstruction::InvokeWord(name));
+                    Ok(())
+                }
+                ForestInstruction::Input => match self.input.pop_front() {
+                    Some(v) => {
+                        self.stack.push(v);
+                        Ok(())
+                    }
+                    None => {
+                        self.program.push(ForestInstruction::Input);
+                        Err(ForestError::WaitingForInput)
+                    }
+                },
                 ForestInstruction::If => {
+                    if self.stack.is_empty() {
+                        return Err(ForestError::Underflow);
+                    }
                     if self.stack[self.stack.len() - 1] == ForestValue::Nil {
                         let mut layers = 1;
                         while layers != 0 {
@@ -595,16 +1588,33 @@ impl ForestRuntime {
                                     ForestInstruction::IfEnd => {
                                         layers -= 1;
                                     }
+                                    // The else branch runs instead.
+                                    ForestInstruction::Else if layers == 1 => {
+                                        layers = 0;
+                                    }
                                     _ => {}
                                 }
                             } else {
-                                return Err(ForestError::EndedWithoutHalting);
+                                return Err(ForestError::UnbalancedIfEnd);
                             }
                         }
                     }
                     Ok(())
                 }
                 ForestInstruction::IfEnd => Ok(()),
+                // Reaching an else means the if branch ran, so skip the else branch.
+                ForestInstruction::Else => {
+                    let mut layers = 1;
+                    while layers != 0 {
+                        match self.program.pop() {
+                            Some(ForestInstruction::If) => layers += 1,
+                            Some(ForestInstruction::IfEnd) => layers -= 1,
+                            Some(_) => {}
+                            None => return Err(ForestError::UnbalancedIfEnd),
+                        }
+                    }
+                    Ok(())
+                }
                 ForestInstruction::Loop => {
                     self.jumplist.push(self.program.clone());
                     Ok(())
@@ -636,6 +1646,87 @@ impl ForestRuntime {
                             return Err(ForestError::UnbalancedLoopEnd);
                         }
                     }
+                    // The loop is left for good, so it won't jump back anymore.
+                    self.jumplist.pop();
+                    // So are the `try`s in it, whose catches were just popped.
+                    while self
+                        .try_frames
+                        .last()
+                        .is_some_and(|f| f.catch_at >= self.program.len())
+                    {
+                        self.try_frames.pop();
+                    }
+                    Ok(())
+                }
+                ForestInstruction::LoopStep => {
+                    let step = match self.stack.pop() {
+                        Some(ForestValue::Int(i)) => i,
+                        Some(v) => return Err(ForestError::TypeMismatch(v, ForestValue::Int(0))),
+                        None => return Err(ForestError::Underflow),
+                    };
+                    let len = self.return_stack.len();
+                    if len < 2 {
+                        return Err(ForestError::ReturnStackUnderflow);
+                    }
+                    let (index, limit) =
+                        match (&self.return_stack[len - 1], &self.return_stack[len - 2]) {
+                            (ForestValue::Int(index), ForestValue::Int(limit)) => (*index, *limit),
+                            (ForestValue::Int(_), v) | (v, _) => {
+                                return Err(ForestError::TypeMismatch(
+                                    v.clone(),
+                                    ForestValue::Int(0),
+                                ));
+                            }
+                        };
+                    let next = index + step;
+                    self.return_stack[len - 1] = ForestValue::Int(next);
+                    // Counting down includes the limit, like in other Forths.
+                    let done = if step >= 0 {
+                        next >= limit
+                    } else {
+                        next < limit
+                    };
+                    self.stack.push(if done {
+                        ForestValue::Int(1)
+                    } else {
+                        ForestValue::Nil
+                    });
+                    Ok(())
+                }
+                ForestInstruction::LoopIndex(depth) => {
+                    // Each enclosing `do` keeps its limit and index on the return stack.
+                    match self.return_stack.len().checked_sub(1 + depth * 2) {
+                        Some(idx) => {
+                            self.stack.push(self.return_stack[idx].clone());
+                            Ok(())
+                        }
+                        None => Err(ForestError::ReturnStackUnderflow),
+                    }
+                }
+                ForestInstruction::ForEachNext => {
+                    let len = self.return_stack.len();
+                    if len < 2 {
+                        return Err(ForestError::ReturnStackUnderflow);
+                    }
+                    let pair = match (&self.return_stack[len - 1], &self.return_stack[len - 2]) {
+                        (ForestValue::Int(index), ForestValue::Table(t)) => t.get(*index as usize),
+                        (ForestValue::Int(_), v) | (v, _) => {
+                            return Err(ForestError::TypeMismatch(
+                                v.clone(),
+                                ForestValue::Table(vec![]),
+                            ));
+                        }
+                    };
+                    // The pair is pushed under a flag saying whether the table is done.
+                    match pair.cloned() {
+                        Some(TablePair { key, value }) => {
+                            self.stack.extend([key, value, ForestValue::Nil]);
+                            if let ForestValue::Int(index) = &mut self.return_stack[len - 1] {
+                                *index += 1;
+                            }
+                        }
+                        None => self.stack.push(ForestValue::Int(1)),
+                    }
                     Ok(())
                 }
                 ForestInstruction::MakeWord(name) => {
@@ -663,22 +1754,7 @@ impl ForestRuntime {
                             return Err(ForestError::UnbalancedWordEnd);
                         }
                     }
-                    match self.wordlist.get(&name) {
-                        Some(v) => {
-                            if v.is_constant {
-                                return Err(ForestError::ReassigningConstant(name));
-                            }
-                        }
-                        None => {}
-                    }
-                    self.wordlist.insert(
-                        name,
-                        Word {
-                            instructions,
-                            is_constant: true,
-                        },
-                    );
-                    Ok(())
+                    self.define(name, instructions, true)
                 }
                 ForestInstruction::MakeWordVar(name) => {
                     let mut instructions: Vec<ForestInstruction> = Vec::new();
@@ -705,80 +1781,97 @@ impl ForestRuntime {
                             return Err(ForestError::UnbalancedWordEnd);
                         }
                     }
-                    match self.wordlist.get(&name) {
-                        Some(v) => {
-                            if v.is_constant {
-                                return Err(ForestError::ReassigningConstant(name));
-                            }
-                        }
-                        None => {}
-                    }
-                    self.wordlist.insert(
-                        name,
-                        Word {
-                            instructions,
-                            is_constant: false,
-                        },
-                    );
-                    Ok(())
+                    self.define(name, instructions, false)
                 }
                 ForestInstruction::EndWord => Ok(()),
                 ForestInstruction::InvokeWord(name) => {
-                    let instrs = match self.wordlist.get(&name) {
-                        Some(v) => v.instructions.clone(),
-                        None => {
-                            return Err(ForestError::UseOfUndeclaredWord(name));
-                        }
+                    let name = self.resolve_word(&name)?;
+                    let word = &self.wordlist[&name];
+                    let instrs = word.instructions.clone();
+                    let module = word.module.clone();
+                    // Plain values don't look anything up, so they need no scope.
+                    let needs_scope = instrs
+                        .iter()
+                        .any(|i| !matches!(i, ForestInstruction::Push(_)));
+                    if needs_scope && module != self.current_module() {
+                        self.program.push(ForestInstruction::ExitScope);
+                        self.push_instrs(&instrs);
+                        self.program.push(ForestInstruction::EnterScope(module));
+                    } else {
+                        self.push_instrs(&instrs);
+                    }
+                    Ok(())
+                }
+                ForestInstruction::Module(name) => {
+                    let module = match self.current_module() {
+                        Some(m) => format!("{m}.{name}"),
+                        None => name,
                     };
-                    self.push_instrs(&instrs);
+                    self.scopes.push(Scope {
+                        module: Some(module),
+                        private: false,
+                    });
+                    Ok(())
+                }
+                ForestInstruction::EnterScope(module) => {
+                    self.scopes.push(Scope {
+                        module,
+                        private: false,
+                    });
+                    Ok(())
+                }
+                ForestInstruction::ExitScope => {
+                    self.scopes.pop();
+                    Ok(())
+                }
+                ForestInstruction::Private | ForestInstruction::Public => {
+                    let private = matches!(inst, ForestInstruction::Private);
+                    match self.scopes.last_mut() {
+                        Some(s) if s.module.is_some() => {
+                            s.private = private;
+                            Ok(())
+                        }
+                        _ => Err(ForestError::OutsideModule(
+                            if private { "private" } else { "public" }.to_string(),
+                        )),
+                    }
+                }
+                ForestInstruction::Using(name) => {
+                    let module = self.aliases.get(&name).cloned().unwrap_or(name);
+                    self.search_order.retain(|m| *m != module);
+                    self.search_order.push(module);
+                    Ok(())
+                }
+                ForestInstruction::Alias(alias, module) => {
+                    self.aliases.insert(alias, module);
                     Ok(())
                 }
                 ForestInstruction::Set(name) => {
-                    if self.stack.len() < 1 {
+                    if self.stack.is_empty() {
                         Err(ForestError::Underflow)
                     } else {
-                        match self.wordlist.get(&name) {
-                            Some(v) => {
-                                if v.is_constant {
-                                    return Err(ForestError::ReassigningConstant(name));
-                                }
-                            }
-                            None => {}
-                        }
                         let v = self.stack.pop().unwrap();
-                        self.wordlist.insert(
-                            name,
-                            Word {
-                                instructions: vec![ForestInstruction::Push(v)],
-                                is_constant: true,
-                            },
-                        );
-                        Ok(())
+                        self.define(name, vec![ForestInstruction::Push(v)], true)
                     }
                 }
                 ForestInstruction::SetVar(name) => {
-                    if self.stack.len() < 1 {
+                    if self.stack.is_empty() {
                         Err(ForestError::Underflow)
                     } else {
-                        match self.wordlist.get(&name) {
-                            Some(v) => {
-                                if v.is_constant {
-                                    return Err(ForestError::ReassigningConstant(name));
-                                }
-                            }
-                            None => {}
-                        }
                         let v = self.stack.pop().unwrap();
-                        self.wordlist.insert(
-                            name,
-                            Word {
-                                instructions: vec![ForestInstruction::Push(v)],
-                                is_constant: false,
-                            },
-                        );
-                        Ok(())
+                        self.define(name, vec![ForestInstruction::Push(v)], false)
                     }
                 }
+                ForestInstruction::Shadow(name) => {
+                    let (qualified, _, _) = self.qualify(&name);
+                    let word = self.wordlist.get(&qualified).cloned();
+                    self.shadowed.push((qualified, word));
+                    Ok(())
+                }
+                ForestInstruction::Unshadow => {
+                    self.unshadow(self.shadowed.len().saturating_sub(1));
+                    Ok(())
+                }
                 ForestInstruction::Include => {
                     let filename = match self.stack.pop() {
                         Some(s) => match s {
@@ -792,32 +1885,80 @@ impl ForestRuntime {
                         },
                         None => return Err(ForestError::Underflow),
                     };
-                    let mut file = match File::open(&filename) {
-                        Ok(f) => f,
-                        Err(e) => {
-                            eprintln!("Could not find file, reason: {e}");
-                            return Err(ForestError::FileNotFound);
-                        }
-                    };
-                    let mut includeprogram = String::new();
-                    match file.read_to_string(&mut includeprogram) {
-                        Ok(_) => {}
-                        Err(e) => {
-                            eprintln!("Could not read file, reason: {e}");
-                            return Err(ForestError::ErrorReadingFile);
+                    let path = self.find_include(&filename)?;
+                    if self.include_stack.contains(&path) {
+                        return Err(ForestError::CyclicInclude(path.display().to_string()));
+                    }
+                    if self.included.contains(&path) {
+                        return Ok(());
+                    }
+                    match compile(&self.read_include(&path)?, Some(&path)) {
+                        Ok(p) => {
+                            self.program.push(ForestInstruction::EndInclude);
+                            self.push_instrs(&p);
                         }
-                    };
-                    match compile(&includeprogram) {
-                        Ok(p) => self.push_instrs(&p),
                         Err(e) => {
                             return Err(ForestError::IncludeCompileError(e));
                         }
                     };
+                    self.included.insert(path.clone());
+                    self.include_stack.push(path);
+                    Ok(())
+                }
+                ForestInstruction::EndInclude => {
+                    self.include_stack.pop();
                     Ok(())
                 }
+                ForestInstruction::NextToken => {
+                    if self.emitted.is_none() {
+                        return Err(ForestError::CompileTimeOnly("next-token".to_string()));
+                    }
+                    match self.tokens.pop_front() {
+                        Some(t) => {
+                            self.stack.push(t);
+                            Ok(())
+                        }
+                        None => {
+                            self.program.push(ForestInstruction::NextToken);
+                            Err(ForestError::WaitingForToken)
+                        }
+                    }
+                }
+                ForestInstruction::Emit => {
+                    let value = self.stack.pop().ok_or(ForestError::Underflow)?;
+                    match self.emitted.as_mut() {
+                        Some(emitted) => {
+                            emitted.push(Emitted::Value(value));
+                            Ok(())
+                        }
+                        None => Err(ForestError::CompileTimeOnly("emit".to_string())),
+                    }
+                }
+                ForestInstruction::EmitToken => {
+                    let source = self.pop_string()?;
+                    match self.emitted.as_mut() {
+                        Some(emitted) => {
+                            emitted.push(Emitted::Source(source));
+                            Ok(())
+                        }
+                        None => Err(ForestError::CompileTimeOnly("emit-token".to_string())),
+                    }
+                }
+                // Evaluated code runs as if it was written in place of `eval`.
+                ForestInstruction::Eval => {
+                    let source = self.pop_string()?;
+                    match compile(&source, self.include_stack.last().map(PathBuf::as_path)) {
+                        Ok(p) => {
+                            self.push_instrs(&p);
+                            Ok(())
+                        }
+                        Err(e) => Err(ForestError::EvalCompileError(e)),
+                    }
+                }
             }
         } else {
-            return Err(ForestError::EndedWithoutHalting);
+            // Running out of instructions is the same as `exit`ing.
+            Err(ForestError::Halt(0))
         }
     }
 
@@ -826,8 +1967,15 @@ impl ForestRuntime {
         for val in &self.stack {
             println!("  {}", val);
         }
-        let inst = &self.program[self.program.len() - 1];
+        let inst = match self.program.last() {
+            Some(i) => i,
+            None => return Err(ForestDumpError::ProgramidxOOB),
+        };
         println!("Current instruction: {}", inst);
+        println!("Return stack: ");
+        for val in &self.return_stack {
+            println!("  {}", val);
+        }
         println!("Jumplists: {}", self.jumplist.len());
         println!("Wordlist:");
         self.wordlist.iter().for_each(|w| {
@@ -843,28 +1991,171 @@ impl ForestRuntime {
     }
 }
 
+/// Runs a runtime to the end, reading any input it asks for from stdin and
+/// printing any error. Evaluates to the exit status, or the error.
 #[macro_export]
 macro_rules! execute_runtime {
     ($runtime_name: ident, $dump: expr) => {
         loop {
             if $dump {
-                match $runtime_name.dump() {
-                    Ok(_) => {}
-                    Err(_) => break,
-                }
+                _ = $runtime_name.dump();
             }
-            match $runtime_name.step() {
-                Ok(_) => {}
-                Err(e) => {
-                    match e {
-                        ForestError::Halt => {}
-                        _ => {
-                            eprintln!("{}", e);
+            match $runtime_name.run_steps(1) {
+                ForestStatus::Running => {}
+                ForestStatus::WaitingForInput => {
+                    _ = std::io::Write::flush(&mut std::io::stdout());
+                    let mut line = String::new();
+                    match std::io::stdin().read_line(&mut line) {
+                        Ok(0) | Err(_) => $runtime_name.provide_input(None),
+                        Ok(_) => {
+                            $runtime_name.provide_input(Some(line.trim_end_matches(['\r', '\n'])))
                         }
-                    };
-                    break;
+                    }
+                }
+                // Only runtimes made for compiling ask for tokens.
+                ForestStatus::WaitingForToken => $runtime_name.provide_token(None),
+                ForestStatus::Halted(code) => break Ok(code),
+                ForestStatus::Errored(e) => {
+                    match $runtime_name.location() {
+                        Some(location) => eprintln!("{location}: {e}"),
+                        None => eprintln!("{}", e),
+                    }
+                    break Err(e);
                 }
             };
         }
     };
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn runtime(source: &str) -> ForestRuntime {
+        match compile(source, None) {
+            Ok(program) => ForestRuntime::new(&program),
+            Err(e) => panic!("the test program doesn't compile: {e}"),
+        }
+    }
+
+    fn int(i: i64) -> ForestValue {
+        ForestValue::Int(i)
+    }
+
+    #[test]
+    fn run_steps_pauses_and_resumes() {
+        let mut runtime = runtime("1 2 + 3 *");
+        assert!(matches!(runtime.run_steps(2), ForestStatus::Running));
+        assert!(runtime.stack() == [int(1), int(2)]);
+        assert!(matches!(runtime.run_steps(1), ForestStatus::Running));
+        assert!(runtime.stack() == [int(3)]);
+        assert!(matches!(runtime.run_steps(100), ForestStatus::Halted(0)));
+        assert!(runtime.stack() == [int(9)]);
+    }
+
+    #[test]
+    fn run_until_stops_before_the_instruction() {
+        let mut runtime = runtime("1 2 swap 3");
+        let status =
+            runtime.run_until(|r| matches!(r.next_instruction(), Some(ForestInstruction::Swap)));
+        assert!(matches!(status, ForestStatus::Running));
+        assert!(runtime.stack() == [int(1), int(2)]);
+        assert!(matches!(
+            runtime.run_until(|_| false),
+            ForestStatus::Halted(0)
+        ));
+        assert!(runtime.stack() == [int(2), int(1), int(3)]);
+    }
+
+    #[test]
+    fn input_waits_until_it_is_provided() {
+        let mut runtime = runtime("input input");
+        assert!(matches!(
+            runtime.run_steps(10),
+            ForestStatus::WaitingForInput
+        ));
+        // Waiting again without input changes nothing.
+        assert!(matches!(
+            runtime.run_steps(10),
+            ForestStatus::WaitingForInput
+        ));
+        assert!(runtime.stack().is_empty());
+        runtime.provide_input(Some("line"));
+        assert!(matches!(
+            runtime.run_steps(10),
+            ForestStatus::WaitingForInput
+        ));
+        runtime.provide_input(None);
+        assert!(matches!(runtime.run_steps(10), ForestStatus::Halted(0)));
+        assert!(runtime.stack() == [ForestValue::String("line".to_string()), ForestValue::Nil]);
+    }
+
+    #[test]
+    fn errors_keep_the_state() {
+        let mut runtime = runtime("5 1 \"a\" +");
+        assert!(matches!(
+            runtime.run_steps(10),
+            ForestStatus::Errored(ForestError::TypeMismatch(_, _))
+        ));
+        assert!(runtime.stack().first() == Some(&int(5)));
+    }
+
+    #[test]
+    fn errors_are_located_where_the_program_called_them() {
+        let source = ": bad ( -- ) 1 \"a\" + ;\n[ 1 if break ifend ]\ntry bad catch drop end\n\"std\" include bad";
+        let (program, positions) = match crate::compile::compile_mapped(source, None) {
+            Ok(c) => c,
+            Err(e) => panic!("the test program doesn't compile: {e}"),
+        };
+        let mut runtime = ForestRuntime::new(&program);
+        runtime.set_source_map("main.frt", &positions);
+        assert!(runtime.location().is_none());
+        assert!(matches!(runtime.run_steps(1000), ForestStatus::Errored(_)));
+        assert_eq!(runtime.location().as_deref(), Some("main.frt:4:15"));
+    }
+
+    #[test]
+    fn expect_error_fails_on_undefined_words() {
+        let mut runtime = runtime("\"no-such-word\" expect-error");
+        assert!(matches!(
+            runtime.run_steps(10),
+            ForestStatus::Errored(ForestError::UseOfUndeclaredWord(_))
+        ));
+    }
+
+    #[test]
+    fn catch_sees_the_stack_from_before_the_try() {
+        let mut runtime = runtime(
+            "1 2 try drop drop \"x\" throw catch drop end 3 >r try r> drop 0 nil + catch end",
+        );
+        assert!(matches!(runtime.run_steps(100), ForestStatus::Halted(0)));
+        assert!(runtime.stack().len() == 3 && runtime.stack()[..2] == [int(1), int(2)]);
+        assert!(runtime.return_stack == [int(3)]);
+    }
+
+    #[test]
+    fn caught_errors_are_located_in_the_source() {
+        let source = "1\ntry 0 nil + catch \"location\" get end";
+        let (program, positions) = match crate::compile::compile_mapped(source, None) {
+            Ok(c) => c,
+            Err(e) => panic!("the test program doesn't compile: {e}"),
+        };
+        let mut runtime = ForestRuntime::new(&program);
+        runtime.set_source_map("main.frt", &positions);
+        assert!(matches!(runtime.run_steps(100), ForestStatus::Halted(0)));
+        assert!(runtime.stack().last() == Some(&ForestValue::String("main.frt:2:11".to_string())));
+    }
+
+    #[test]
+    fn exit_halts_with_its_status() {
+        let mut exits = runtime("3 exit 4");
+        assert!(matches!(exits.run_steps(10), ForestStatus::Halted(0)));
+        assert_eq!(exits.stack().len(), 1);
+        let mut runtime = runtime("3 exit-with 4");
+        assert!(matches!(runtime.run_steps(10), ForestStatus::Halted(3)));
+        assert!(matches!(
+            runtime.next_instruction(),
+            Some(ForestInstruction::Push(_))
+        ));
+    }
+}
@@ -0,0 +1,239 @@
+use crate::compile::ForestCompileError;
+use crate::lexer::{Token, TokenKind, lex};
+use crate::test_runner::collect_files;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Words that start a block, indenting the lines after them.
+const OPENERS: &[&str] = &[
+    ":", "::", "if", "[", "do", "begin", "for", "case", "of", "try", "module",
+];
+/// Words that end a block.
+pub const CLOSERS: &[&str] = &[
+    ";", "ifend", "then", "]", "loop", "+loop", "until", "repeat", "endof", "endcase", "end",
+];
+/// Words in the middle of a block, which sit at the same indentation as the
+/// word that started it.
+pub const MIDDLES: &[&str] = &["else", "catch", "while"];
+
+/// How many of the tokens after a word are names rather than code.
+fn names_after(word: &str) -> usize {
+    match word {
+        ":" | "::" | "->" | "=>" | "module" | "using" | "postpone" => 1,
+        "for" => 2,
+        "import" => 3,
+        _ => 0,
+    }
+}
+
+/// How many lines holding a single token each it takes for them to be put
+/// on one line. Two are often a pair of statements, like `drop` and `exit`.
+const MIN_RUN: usize = 3;
+
+/// Whether a line is a single token that can share a line with others, so
+/// runs of them can be put on one line.
+fn is_lone(line: &[&Token]) -> bool {
+    match line {
+        [token] => {
+            !matches!(token.kind, TokenKind::Comment(_))
+                && !token.raw.contains('\n')
+                && ![OPENERS, CLOSERS, MIDDLES]
+                    .iter()
+                    .any(|words| words.contains(&token.raw.as_str()))
+        }
+        _ => false,
+    }
+}
+
+/// Where the line after a token starts in the source.
+fn end_line(token: &Token) -> usize {
+    token.position.line + token.raw.matches('\n').count()
+}
+
+/// Re-emits source with every line indented by a tab per block it is in.
+/// Line breaks and comments are kept, except that runs of three or more lines
+/// holding a single token each are put on one line. Runs of blank lines are squashed
+/// into one, and tokens on the same line are separated by a single space,
+/// unless they were written with nothing between them.
+pub fn format_source(source: &str) -> Result<String, ForestCompileError> {
+    let tokens = lex(source)?;
+    let mut lines: Vec<Vec<&Token>> = Vec::new();
+    let mut last_line = 0;
+    for token in &tokens {
+        if lines.is_empty() || token.position.line != last_line {
+            lines.push(vec![]);
+        }
+        // A token spanning lines, like a string with a line break in it,
+        // moves the following tokens down.
+        last_line = end_line(token);
+        lines.last_mut().unwrap().push(token);
+    }
+    // Lines are grouped into runs of adjacent lone lines first, and other
+    // lines are runs of their own.
+    let mut runs: Vec<Vec<Vec<&Token>>> = Vec::new();
+    for line in lines {
+        match runs.last_mut() {
+            Some(run)
+                if is_lone(&line)
+                    && is_lone(&run[0])
+                    && line[0].position.line == end_line(run[run.len() - 1][0]) + 1 =>
+            {
+                run.push(line)
+            }
+            _ => runs.push(vec![line]),
+        }
+    }
+    let mut joined: Vec<Vec<&Token>> = Vec::new();
+    for run in runs {
+        if run.len() >= MIN_RUN {
+            joined.push(run.concat());
+        } else {
+            joined.extend(run);
+        }
+    }
+    let mut formatted = String::new();
+    let mut depth: usize = 0;
+    let mut skip = 0;
+    let mut previous_end = 0;
+    for line in joined {
+        let first = line[0];
+        if previous_end != 0 && first.position.line > previous_end + 1 {
+            formatted.push('\n');
+        }
+        // Each closer at the start of the line takes it out of a block, and
+        // a middle word after them sits with the word that started its block.
+        let mut dedent = 0;
+        if skip == 0 {
+            for token in &line {
+                let raw = token.raw.as_str();
+                if !matches!(token.kind, TokenKind::Word(_)) {
+                    break;
+                } else if CLOSERS.contains(&raw) {
+                    dedent += 1;
+                } else {
+                    dedent += MIDDLES.contains(&raw) as usize;
+                    break;
+                }
+            }
+        }
+        formatted.push_str(&"\t".repeat(depth.saturating_sub(dedent)));
+        let mut previous: Option<&Token> = None;
+        for token in &line {
+            if let Some(previous) = previous {
+                let touching = previous.position.line == token.position.line
+                    && !previous.raw.contains('\n')
+                    && previous.position.column + previous.raw.chars().count()
+                        == token.position.column;
+                if !touching {
+                    formatted.push(' ');
+                }
+            }
+            formatted.push_str(&token.raw);
+            previous = Some(token);
+        }
+        formatted.push('\n');
+        for token in &line {
+            if !matches!(token.kind, TokenKind::Word(_)) {
+                continue;
+            }
+            if skip > 0 {
+                skip -= 1;
+                continue;
+            }
+            let raw = token.raw.as_str();
+            if OPENERS.contains(&raw) {
+                depth += 1;
+            } else if CLOSERS.contains(&raw) {
+                depth = depth.saturating_sub(1);
+            }
+            skip = names_after(raw);
+        }
+        previous_end = end_line(line[line.len() - 1]);
+    }
+    Ok(formatted)
+}
+
+/// Formats every `.frt` file in `paths` (or the current directory) in place.
+/// With `--check`, files are only reported, and it fails if any of them
+/// would change. Files that can't be split into tokens are skipped.
+pub fn run_fmt(args: &[String]) -> Result<(), ()> {
+    let check = args.iter().any(|a| a == "--check");
+    let mut files: Vec<PathBuf> = Vec::new();
+    let paths: Vec<&str> = args
+        .iter()
+        .filter(|a| *a != "--check")
+        .map(String::as_str)
+        .collect();
+    if paths.is_empty() {
+        collect_files(Path::new("."), &mut files);
+    } else {
+        paths
+            .iter()
+            .for_each(|p| collect_files(Path::new(p), &mut files));
+    }
+    let mut failed = false;
+    for file in files {
+        let source = match fs::read_to_string(&file) {
+            Ok(s) => s,
+            Err(e) => {
+                eprintln!("{}: could not read file, reason: {e}", file.display());
+                failed = true;
+                continue;
+            }
+        };
+        // Files that can't be split into tokens, like the ones testing
+        // those errors, are left for the compiler to report.
+        let formatted = match format_source(&source) {
+            Ok(f) => f,
+            Err(e) => {
+                eprintln!("{}: skipped, reason: {e}", file.display());
+                continue;
+            }
+        };
+        if formatted == source {
+            continue;
+        }
+        if check {
+            println!("{} is not formatted", file.display());
+            failed = true;
+        } else if let Err(e) = fs::write(&file, formatted) {
+            eprintln!("{}: could not write file, reason: {e}", file.display());
+            failed = true;
+        } else {
+            println!("Formatted {}", file.display());
+        }
+    }
+    if failed { Err(()) } else { Ok(()) }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Formats each `.before` file in tests/fmt, which should give its
+    /// `.after` file, and formatting that again should change nothing.
+    #[test]
+    fn fixtures() {
+        let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fmt");
+        let mut checked = 0;
+        for entry in fs::read_dir(dir).unwrap() {
+            let before = entry.unwrap().path();
+            if before.extension().is_none_or(|e| e != "before") {
+                continue;
+            }
+            let formatted = match format_source(&fs::read_to_string(&before).unwrap()) {
+                Ok(f) => f,
+                Err(e) => panic!("{}: {e}", before.display()),
+            };
+            let after = fs::read_to_string(before.with_extension("after")).unwrap();
+            assert_eq!(formatted, after, "{}", before.display());
+            assert!(
+                format_source(&after).is_ok_and(|f| f == after),
+                "{} changes when formatted again",
+                before.display()
+            );
+            checked += 1;
+        }
+        assert!(checked > 0);
+    }
+}
@@ -0,0 +1,138 @@
+use crate::test_runner::collect_files;
+use std::env;
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+/// Directories checked by `forest golden` when it isn't given any paths.
+const DEFAULT_PATHS: &[&str] = &["examples", "tests/golden", "tests/tools"];
+
+/// Runs `forest run` on a file, returning what it printed. Anything printed
+/// to stderr, and a non-zero exit status, follow separator lines. A sibling
+/// `.in` file is used as stdin, and a sibling `.args` file holds the
+/// arguments to use instead of `run`, for checking the other tools.
+fn capture(exe: &Path, file: &Path) -> Result<String, String> {
+    let stdin = match File::open(file.with_extension("in")) {
+        Ok(f) => Stdio::from(f),
+        Err(_) => Stdio::null(),
+    };
+    let args = fs::read_to_string(file.with_extension("args")).unwrap_or("run".to_string());
+    let output = Command::new(exe)
+        .args(args.split_whitespace())
+        .arg(file)
+        .stdin(stdin)
+        .output()
+        .map_err(|e| format!("could not run forest, reason: {e}"))?;
+    let mut captured = String::from_utf8_lossy(&output.stdout).to_string();
+    if !output.stderr.is_empty() {
+        if !captured.is_empty() && !captured.ends_with('\n') {
+            captured.push('\n');
+        }
+        captured.push_str("--- stderr ---\n");
+        captured.push_str(&String::from_utf8_lossy(&output.stderr));
+    }
+    match output.status.code() {
+        Some(0) => {}
+        code => {
+            if !captured.is_empty() && !captured.ends_with('\n') {
+                captured.push('\n');
+            }
+            match code {
+                Some(c) => captured.push_str(&format!("--- exit status {c} ---\n")),
+                None => captured.push_str("--- killed by a signal ---\n"),
+            }
+        }
+    }
+    Ok(captured)
+}
+
+/// Runs each `.frt` file and compares its output to the sibling `.out` file,
+/// or rewrites the `.out` files when `--update` is passed.
+pub fn run_golden(args: &[String]) -> Result<(), ()> {
+    let update = args.iter().any(|a| a == "--update");
+    let mut files: Vec<PathBuf> = Vec::new();
+    let paths: Vec<&str> = args
+        .iter()
+        .filter(|a| *a != "--update")
+        .map(String::as_str)
+        .collect();
+    if paths.is_empty() {
+        DEFAULT_PATHS
+            .iter()
+            .for_each(|p| collect_files(Path::new(p), &mut files));
+    } else {
+        paths
+            .iter()
+            .for_each(|p| collect_files(Path::new(p), &mut files));
+    }
+    let exe = match env::current_exe() {
+        Ok(e) => e,
+        Err(e) => {
+            eprintln!("Could not find the forest executable, reason: {e}");
+            return Err(());
+        }
+    };
+    let mut passed = 0;
+    let mut failed = 0;
+    for file in files {
+        let expected_path = file.with_extension("out");
+        let actual = match capture(&exe, &file) {
+            Ok(a) => a,
+            Err(e) => {
+                println!("FAIL {}: {e}", file.display());
+                failed += 1;
+                continue;
+            }
+        };
+        if update {
+            match fs::write(&expected_path, &actual) {
+                Ok(_) => println!("UPDATED {}", expected_path.display()),
+                Err(e) => {
+                    println!(
+                        "FAIL {}: could not write, reason: {e}",
+                        expected_path.display()
+                    );
+                    failed += 1;
+                }
+            }
+            continue;
+        }
+        let expected = match fs::read_to_string(&expected_path) {
+            Ok(e) => e,
+            Err(_) => {
+                println!(
+                    "FAIL {}: no {} (run with --update to create it)",
+                    file.display(),
+                    expected_path.display()
+                );
+                failed += 1;
+                continue;
+            }
+        };
+        if expected == actual {
+            println!("PASS {}", file.display());
+            passed += 1;
+        } else {
+            println!("FAIL {}", file.display());
+            let mismatch = expected
+                .lines()
+                .zip(actual.lines())
+                .position(|(e, a)| e != a)
+                .unwrap_or(expected.lines().count().min(actual.lines().count()));
+            println!("  first difference on line {}:", mismatch + 1);
+            println!(
+                "  expected: {:?}",
+                expected.lines().nth(mismatch).unwrap_or("<end of output>")
+            );
+            println!(
+                "  got:      {:?}",
+                actual.lines().nth(mismatch).unwrap_or("<end of output>")
+            );
+            failed += 1;
+        }
+    }
+    if !update {
+        println!("\n{passed} passed, {failed} failed");
+    }
+    if failed == 0 { Ok(()) } else { Err(()) }
+}
@@ -0,0 +1,257 @@
+use crate::compile::ForestCompileError;
+use std::fmt;
+use std::iter::Peekable;
+use std::str::CharIndices;
+
+/// A place in the source, counting lines and columns from 1.
+#[derive(Clone, Copy, PartialEq)]
+pub struct Position {
+    pub line: usize,
+    pub column: usize,
+}
+
+impl fmt::Display for Position {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}", self.line, self.column)
+    }
+}
+
+#[allow(dead_code)]
+#[derive(Clone)]
+pub enum TokenKind {
+    Word(String),
+    Str(String),
+    Char(char),
+    /// The text of a comment, kept for tools like the formatter.
+    Comment(String),
+}
+
+#[derive(Clone)]
+pub struct Token {
+    pub kind: TokenKind,
+    /// The token exactly as written in the source.
+    pub raw: String,
+    pub position: Position,
+}
+
+struct Lexer<'a> {
+    source: &'a str,
+    chars: Peekable<CharIndices<'a>>,
+    line: usize,
+    column: usize,
+}
+
+impl<'a> Lexer<'a> {
+    fn position(&self) -> Position {
+        Position {
+            line: self.line,
+            column: self.column,
+        }
+    }
+
+    fn peek(&mut self) -> Option<char> {
+        self.chars.peek().map(|(_, c)| *c)
+    }
+
+    fn offset(&mut self) -> usize {
+        self.chars
+            .peek()
+            .map(|(i, _)| *i)
+            .unwrap_or(self.source.len())
+    }
+
+    fn bump(&mut self) -> Option<char> {
+        let (_, c) = self.chars.next()?;
+        if c == '\n' {
+            self.line += 1;
+            self.column = 1;
+        } else {
+            self.column += 1;
+        }
+        Some(c)
+    }
+
+    fn word(&mut self) -> TokenKind {
+        let start = self.offset();
+        while self.peek().is_some_and(|c| !c.is_whitespace()) {
+            self.bump();
+        }
+        TokenKind::Word(self.source[start..self.offset()].to_string())
+    }
+
+    /// Reads `count` hex digits, or as many as there are (up to 6) if
+    /// `count` is `None`.
+    fn hex_digits(&mut self, count: Option<usize>) -> Option<u32> {
+        let mut digits = String::new();
+        while digits.len() < count.unwrap_or(6) {
+            match self.peek() {
+                Some(c) if c.is_ascii_hexdigit() => {
+                    digits.push(c);
+                    self.bump();
+                }
+                _ => break,
+            }
+        }
+        if digits.is_empty() || count.is_some_and(|n| digits.len() != n) {
+            return None;
+        }
+        u32::from_str_radix(&digits, 16).ok()
+    }
+
+    fn escape(&mut self) -> Result<char, ForestCompileError> {
+        let start = self.position();
+        let start_offset = self.offset();
+        self.bump();
+        let c = match self.bump() {
+            Some(c) => c,
+            None => return Err(ForestCompileError::UnterminatedString(start)),
+        };
+        let code = match c {
+            'n' => return Ok('\n'),
+            'r' => return Ok('\r'),
+            't' => return Ok('\t'),
+            's' => return Ok(' '),
+            '0' => return Ok('\0'),
+            '\\' => return Ok('\\'),
+            '"' => return Ok('"'),
+            '\'' => return Ok('\''),
+            'x' => self.hex_digits(Some(2)),
+            'u' => {
+                if self.peek() == Some('{') {
+                    self.bump();
+                    let code = self.hex_digits(None);
+                    if self.peek() == Some('}') {
+                        self.bump();
+                        code
+                    } else {
+                        None
+                    }
+                } else {
+                    None
+                }
+            }
+            c => return Err(ForestCompileError::UnknownEscape(c, start)),
+        };
+        match code.and_then(char::from_u32) {
+            Some(c) => Ok(c),
+            None => {
+                let raw = self.source[start_offset..self.offset()].to_string();
+                Err(ForestCompileError::InvalidEscape(raw, start))
+            }
+        }
+    }
+
+    /// Whether the character after the next one ends a token.
+    fn standalone(&self) -> bool {
+        let mut rest = self.chars.clone();
+        rest.next();
+        rest.next().is_none_or(|(_, c)| c.is_whitespace())
+    }
+
+    fn line_comment(&mut self, prefix: usize) -> TokenKind {
+        (0..prefix).for_each(|_| _ = self.bump());
+        let start = self.offset();
+        while self.peek().is_some_and(|c| c != '\n') {
+            self.bump();
+        }
+        TokenKind::Comment(self.source[start..self.offset()].trim().to_string())
+    }
+
+    fn paren_comment(&mut self) -> Result<TokenKind, ForestCompileError> {
+        let start = self.position();
+        self.bump();
+        let text_start = self.offset();
+        while self.peek().is_some_and(|c| c != ')') {
+            self.bump();
+        }
+        if self.peek().is_none() {
+            return Err(ForestCompileError::UnterminatedComment(start));
+        }
+        let text = self.source[text_start..self.offset()].trim().to_string();
+        self.bump();
+        Ok(TokenKind::Comment(text))
+    }
+
+    /// Reads a character literal like `'a'` or `'\n'`, or returns `None`
+    /// without reading anything if the token isn't one.
+    fn char_literal(&mut self) -> Result<Option<TokenKind>, ForestCompileError> {
+        let saved = (self.chars.clone(), self.line, self.column);
+        self.bump();
+        let c = match self.peek() {
+            Some('\\') => Some(self.escape()?),
+            Some(c) if c != '\'' && !c.is_whitespace() || c == ' ' => {
+                self.bump();
+                Some(c)
+            }
+            _ => None,
+        };
+        if let Some(c) = c
+            && self.peek() == Some('\'')
+            && self.standalone()
+        {
+            self.bump();
+            return Ok(Some(TokenKind::Char(c)));
+        }
+        (self.chars, self.line, self.column) = saved;
+        Ok(None)
+    }
+
+    fn string(&mut self) -> Result<TokenKind, ForestCompileError> {
+        let start = self.position();
+        self.bump();
+        let mut value = String::new();
+        loop {
+            match self.peek() {
+                Some('"') => {
+                    self.bump();
+                    return Ok(TokenKind::Str(value));
+                }
+                Some('\\') => value.push(self.escape()?),
+                Some(c) => {
+                    value.push(c);
+                    self.bump();
+                }
+                None => return Err(ForestCompileError::UnterminatedString(start)),
+            }
+        }
+    }
+}
+
+/// Splits source into words, string literals and comments, separated by
+/// whitespace. Strings start with `"` and may contain whitespace and escapes.
+/// Comments are `\ ` to the end of the line, `( ... )`, and a `#!` line at
+/// the very start of the source.
+pub fn lex(source: &str) -> Result<Vec<Token>, ForestCompileError> {
+    let mut lexer = Lexer {
+        source,
+        chars: source.char_indices().peekable(),
+        line: 1,
+        column: 1,
+    };
+    let mut tokens: Vec<Token> = Vec::new();
+    while let Some(c) = lexer.peek() {
+        if c.is_whitespace() {
+            lexer.bump();
+            continue;
+        }
+        let position = lexer.position();
+        let start = lexer.offset();
+        let kind = match c {
+            '"' => lexer.string()?,
+            '\'' => match lexer.char_literal()? {
+                Some(kind) => kind,
+                None => lexer.word(),
+            },
+            '\\' if lexer.standalone() => lexer.line_comment(1),
+            '(' if lexer.standalone() => lexer.paren_comment()?,
+            '#' if start == 0 && source.starts_with("#!") => lexer.line_comment(2),
+            _ => lexer.word(),
+        };
+        tokens.push(Token {
+            kind,
+            raw: source[start..lexer.offset()].to_string(),
+            position,
+        });
+    }
+    Ok(tokens)
+}
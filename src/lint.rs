@@ -0,0 +1,397 @@
+use crate::analysis::{Analysis, Definition, DefinitionKind, Source, visible};
+use crate::compile::check;
+use crate::forest_runtime::{ForestInstruction as fi, is_bundled_source};
+use crate::formatter::{CLOSERS, MIDDLES};
+use crate::lexer::{Position, Token, TokenKind};
+use crate::test_runner::{TEST_PREFIX, collect_files};
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// A comment starting with this allows the rules it names on its line and
+/// the next, like `\ lint: allow unused-word`, or in the whole file if it
+/// comes before any code.
+const ALLOW_COMMENT: &str = "lint: allow";
+
+/// Something `forest lint` looks for. Each has a code that doesn't change,
+/// for allowing it.
+#[derive(Clone, Copy, PartialEq)]
+enum Rule {
+    UnusedWord,
+    UnusedVariable,
+    UndefinedWord,
+    UndroppedFlag,
+    InternalName,
+    UnreachableCode,
+    RedefinedConstant,
+}
+
+impl Rule {
+    const ALL: [Rule; 7] = [
+        Rule::UnusedWord,
+        Rule::UnusedVariable,
+        Rule::UndefinedWord,
+        Rule::UndroppedFlag,
+        Rule::InternalName,
+        Rule::UnreachableCode,
+        Rule::RedefinedConstant,
+    ];
+
+    fn code(self) -> &'static str {
+        match self {
+            Rule::UnusedWord => "unused-word",
+            Rule::UnusedVariable => "unused-variable",
+            Rule::UndefinedWord => "undefined-word",
+            Rule::UndroppedFlag => "undropped-flag",
+            Rule::InternalName => "internal-name",
+            Rule::UnreachableCode => "unreachable-code",
+            Rule::RedefinedConstant => "redefined-constant",
+        }
+    }
+
+    fn from_code(code: &str) -> Option<Rule> {
+        Rule::ALL.into_iter().find(|r| r.code() == code)
+    }
+}
+
+struct Lint {
+    rule: Rule,
+    position: Position,
+    message: String,
+}
+
+/// The tokens of a file that aren't comments.
+fn code(analysis: &Analysis) -> Vec<&Token> {
+    analysis
+        .tokens
+        .iter()
+        .filter(|t| !matches!(t.kind, TokenKind::Comment(_)))
+        .collect()
+}
+
+fn is_word(token: &Token, word: &str) -> bool {
+    matches!(token.kind, TokenKind::Word(_)) && token.raw == word
+}
+
+/// Words and variables that are defined but never used. Strings count as
+/// uses of the word they name, for words like `expect-error`, and tests are
+/// used by `forest test`.
+fn unused(analysis: &Analysis, lints: &mut Vec<Lint>) {
+    let mut reported: HashSet<(&str, Option<&str>)> = HashSet::new();
+    for definition in &analysis.definitions {
+        let is_word = matches!(
+            definition.kind,
+            DefinitionKind::Word | DefinitionKind::ConstantWord
+        );
+        if (is_word && definition.name.starts_with(TEST_PREFIX))
+            || !reported.insert((&definition.name, definition.module.as_deref()))
+        {
+            continue;
+        }
+        let used = analysis.references(definition).any(|t| {
+            !analysis
+                .definitions
+                .iter()
+                .any(|d| d.position == t.position)
+        }) || analysis
+            .tokens
+            .iter()
+            .any(|t| matches!(&t.kind, TokenKind::Str(s) if *s == definition.name));
+        if used {
+            continue;
+        }
+        let (rule, kind) = if is_word {
+            (Rule::UnusedWord, "Word")
+        } else {
+            (Rule::UnusedVariable, "Variable")
+        };
+        lints.push(Lint {
+            rule,
+            position: definition.position,
+            message: format!("{kind} `{}` is never used", definition.name),
+        });
+    }
+}
+
+/// Words invoked that nothing visible from the file defines, which fail
+/// with `UseOfUndeclaredWord` when they run.
+fn undefined(program: &[fi], positions: &[Position], visible: &[&Analysis], lints: &mut Vec<Lint>) {
+    // Names can also be defined by what immediate words emit, and by for loops.
+    let defined: HashSet<&str> = program
+        .iter()
+        .filter_map(|instr| match instr {
+            fi::MakeWord(n) | fi::MakeWordVar(n) | fi::Set(n) | fi::SetVar(n) => Some(n.as_str()),
+            _ => None,
+        })
+        .collect();
+    let mut reported: HashSet<&str> = HashSet::new();
+    for (instr, position) in program.iter().zip(positions) {
+        let fi::InvokeWord(name) = instr else {
+            continue;
+        };
+        // A module prefix may be an alias, so the name alone is enough.
+        let bare = name.rsplit_once('.').map_or(name.as_str(), |(_, n)| n);
+        let is_defined = defined.contains(name.as_str())
+            || defined.contains(bare)
+            || visible
+                .iter()
+                .flat_map(|a| &a.definitions)
+                .any(|d| d.is_named(name) || d.name == bare);
+        if !is_defined && reported.insert(name) {
+            lints.push(Lint {
+                rule: Rule::UndefinedWord,
+                position: *position,
+                message: format!("Word `{name}` is not defined"),
+            });
+        }
+    }
+}
+
+/// `if ... ifend` leaves its flag on the stack, which is usually dropped
+/// right after the `ifend`.
+fn undropped_flags(code: &[&Token], lints: &mut Vec<Lint>) {
+    let mut ifs: Vec<Position> = Vec::new();
+    for (i, token) in code.iter().enumerate() {
+        if is_word(token, "if") {
+            ifs.push(token.position);
+        } else if is_word(token, "then") {
+            ifs.pop();
+        } else if is_word(token, "ifend")
+            && let Some(position) = ifs.pop()
+            && !code.get(i + 1).is_some_and(|t| is_word(t, "drop"))
+        {
+            // `if ... ifend ! if ... ifend` reuses the flag for an else branch.
+            let message = if code.get(i + 1).is_some_and(|t| is_word(t, "!")) {
+                "The flag of this `if` is negated instead of dropped; \
+                 use `if ... else ... then` instead"
+            } else {
+                "The flag of this `if` is never dropped; drop it after `ifend`, \
+                 or use `then` instead"
+            };
+            lints.push(Lint {
+                rule: Rule::UndroppedFlag,
+                position,
+                message: message.to_string(),
+            });
+        }
+    }
+}
+
+/// Variables named like the ones the standard library uses for itself,
+/// which its words overwrite.
+fn internal_names(analysis: &Analysis, lints: &mut Vec<Lint>) {
+    let mut reported: HashSet<&str> = HashSet::new();
+    for definition in &analysis.definitions {
+        if matches!(
+            definition.kind,
+            DefinitionKind::Variable | DefinitionKind::Constant
+        ) && definition.name.starts_with("__")
+            && reported.insert(&definition.name)
+        {
+            lints.push(Lint {
+                rule: Rule::InternalName,
+                position: definition.position,
+                message: format!(
+                    "`{}` is named like the internals of the standard library, whose words may overwrite it",
+                    definition.name
+                ),
+            });
+        }
+    }
+}
+
+/// Code right after an `exit` or `exit-with` in the same block, which never
+/// runs.
+fn unreachable(code: &[&Token], lints: &mut Vec<Lint>) {
+    for (i, token) in code.iter().enumerate() {
+        let named = i > 0
+            && matches!(
+                code[i - 1].raw.as_str(),
+                ":" | "::" | "->" | "=>" | "postpone"
+            );
+        if !(is_word(token, "exit") || is_word(token, "exit-with")) || named {
+            continue;
+        }
+        if let Some(next) = code.get(i + 1)
+            && !(matches!(next.kind, TokenKind::Word(_))
+                && (CLOSERS.contains(&next.raw.as_str()) || MIDDLES.contains(&next.raw.as_str())))
+        {
+            lints.push(Lint {
+                rule: Rule::UnreachableCode,
+                position: next.position,
+                message: format!("This is never run, since it comes after an `{}`", token.raw),
+            });
+        }
+    }
+}
+
+/// Definitions with the name of a constant defined before them, in the file
+/// or in a file it includes, which fail with `ReassigningConstant`.
+fn redefined_constants(analysis: &Analysis, others: &[&Analysis], lints: &mut Vec<Lint>) {
+    let is_constant = |kind| {
+        matches!(
+            kind,
+            DefinitionKind::ConstantWord | DefinitionKind::Constant
+        )
+    };
+    for (i, definition) in analysis.definitions.iter().enumerate() {
+        let same = |d: &&Definition| {
+            is_constant(d.kind) && d.name == definition.name && d.module == definition.module
+        };
+        let earlier = analysis.definitions[..i].iter().find(same);
+        let included = others.iter().flat_map(|a| &a.definitions).find(same);
+        let message = match (earlier, included) {
+            (Some(d), _) => format!(
+                "`{}` is a constant defined on line {}, so it can't be defined again",
+                definition.name, d.position.line
+            ),
+            (None, Some(_)) => format!(
+                "`{}` is a constant defined in an included file, so it can't be defined again",
+                definition.name
+            ),
+            (None, None) => continue,
+        };
+        lints.push(Lint {
+            rule: Rule::RedefinedConstant,
+            position: definition.position,
+            message,
+        });
+    }
+}
+
+/// The rules allowed by comments, as (line, rule) pairs. A line of `None`
+/// allows the rule in the whole file, for comments before any code.
+fn allowed_by_comments(analysis: &Analysis) -> Vec<(Option<usize>, Rule)> {
+    let mut allowed = Vec::new();
+    let mut before_code = true;
+    for token in &analysis.tokens {
+        let TokenKind::Comment(text) = &token.kind else {
+            before_code = false;
+            continue;
+        };
+        let Some(codes) = text.strip_prefix(ALLOW_COMMENT) else {
+            continue;
+        };
+        for rule in codes
+            .split([',', ' '])
+            .filter_map(|c| Rule::from_code(c.trim()))
+        {
+            let line = token.position.line;
+            if before_code {
+                allowed.push((None, rule));
+            } else {
+                allowed.extend([(Some(line), rule), (Some(line + 1), rule)]);
+            }
+        }
+    }
+    allowed
+}
+
+/// Lints a file, printing what is found that isn't allowed. Fails if
+/// anything is, or if the file doesn't compile.
+fn lint_file(path: &Path, allowed: &[Rule]) -> Result<(), ()> {
+    let text = match fs::read_to_string(path) {
+        Ok(t) => t,
+        Err(e) => {
+            eprintln!("{}: could not read file, reason: {e}", path.display());
+            return Err(());
+        }
+    };
+    let (program, positions) = match check(&text, Some(path)) {
+        Ok(c) => c,
+        Err(e) => {
+            eprintln!("{}: {e}", path.display());
+            return Err(());
+        }
+    };
+    // The file compiles, so it comes first, before what it includes.
+    let files = visible(path, |p| fs::read_to_string(p).ok());
+    let Some((file, others)) = files.split_first() else {
+        return Err(());
+    };
+    let analysis = &file.analysis;
+    let code = code(analysis);
+    let all: Vec<&Analysis> = files.iter().map(|f| &f.analysis).collect();
+    // A file can't clash with itself, as when it includes itself under
+    // another path, or when a bundled library is linted with the prelude.
+    let canonical = path.canonicalize().ok();
+    let bundled = is_bundled_source(&text);
+    let included: Vec<&Analysis> = others
+        .iter()
+        .filter(|f| match &f.source {
+            Source::File(p) => p.canonicalize().ok() != canonical,
+            Source::Bundled => !bundled,
+        })
+        .map(|f| &f.analysis)
+        .collect();
+    let mut lints = Vec::new();
+    // A bundled library defines words for other files, and its internals
+    // are its own.
+    if !bundled {
+        unused(analysis, &mut lints);
+        internal_names(analysis, &mut lints);
+    }
+    undefined(&program, &positions, &all, &mut lints);
+    undropped_flags(&code, &mut lints);
+    unreachable(&code, &mut lints);
+    redefined_constants(analysis, &included, &mut lints);
+    let by_comments = allowed_by_comments(analysis);
+    lints.retain(|l| {
+        !allowed.contains(&l.rule)
+            && !by_comments.contains(&(None, l.rule))
+            && !by_comments.contains(&(Some(l.position.line), l.rule))
+    });
+    lints.sort_by_key(|l| (l.position.line, l.position.column));
+    for lint in &lints {
+        println!(
+            "{}:{}: {}: {}",
+            path.display(),
+            lint.position,
+            lint.rule.code(),
+            lint.message
+        );
+    }
+    if lints.is_empty() { Ok(()) } else { Err(()) }
+}
+
+/// Lints every `.frt` file in `paths` (or the current directory). Rules can
+/// be allowed everywhere with `--allow <code>`.
+pub fn run_lint(args: &[String]) -> Result<(), ()> {
+    let mut allowed = Vec::new();
+    let mut paths = Vec::new();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        if arg != "--allow" {
+            paths.push(arg);
+            continue;
+        }
+        match args.next().map(|c| (c, Rule::from_code(c))) {
+            Some((_, Some(rule))) => allowed.push(rule),
+            Some((code, None)) => {
+                let codes: Vec<&str> = Rule::ALL.iter().map(|r| r.code()).collect();
+                eprintln!(
+                    "Unknown lint `{code}`, expected one of: {}",
+                    codes.join(", ")
+                );
+                return Err(());
+            }
+            None => {
+                eprintln!("`--allow` must be followed by the code of a lint");
+                return Err(());
+            }
+        }
+    }
+    let mut files: Vec<PathBuf> = Vec::new();
+    if paths.is_empty() {
+        collect_files(Path::new("."), &mut files);
+    } else {
+        paths
+            .iter()
+            .for_each(|p| collect_files(Path::new(p), &mut files));
+    }
+    let mut failed = false;
+    for file in files {
+        failed |= lint_file(&file, &allowed).is_err();
+    }
+    if failed { Err(()) } else { Ok(()) }
+}
@@ -0,0 +1,620 @@
+use crate::analysis::{Analysis, Definition, DefinitionKind, Source, SourceFile, analyze, visible};
+use crate::compile::{BUILTIN_WORDS, check};
+use crate::json::Json;
+use crate::lexer::Position;
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io::{self, BufRead, Write};
+use std::path::{Path, PathBuf};
+
+// Error codes from the JSON-RPC spec.
+const PARSE_ERROR: i64 = -32700;
+const METHOD_NOT_FOUND: i64 = -32601;
+
+// Kinds of completion items from the LSP spec.
+const COMPLETION_FUNCTION: usize = 3;
+const COMPLETION_VARIABLE: usize = 6;
+const COMPLETION_KEYWORD: usize = 14;
+
+const DIAGNOSTIC_ERROR: usize = 1;
+
+/// A source file and what is known about it.
+struct Document {
+    text: String,
+    /// The analysis of the last version of the text that could be split into
+    /// tokens, so that a typo doesn't make every word unknown.
+    analysis: Option<Analysis>,
+}
+
+struct Server {
+    documents: HashMap<PathBuf, Document>,
+    shut_down: bool,
+    /// Messages for the client that haven't been written yet.
+    outbox: Vec<Json>,
+}
+
+fn uri_to_path(uri: &str) -> PathBuf {
+    let path = uri.strip_prefix("file://").unwrap_or(uri);
+    let mut bytes = Vec::new();
+    let mut chars = path.bytes();
+    while let Some(b) = chars.next() {
+        if b == b'%' {
+            let hex: Vec<u8> = chars.by_ref().take(2).collect();
+            match std::str::from_utf8(&hex)
+                .ok()
+                .and_then(|h| u8::from_str_radix(h, 16).ok())
+            {
+                Some(decoded) => bytes.push(decoded),
+                None => bytes.extend(b"%".iter().chain(&hex)),
+            }
+        } else {
+            bytes.push(b);
+        }
+    }
+    PathBuf::from(String::from_utf8_lossy(&bytes).to_string())
+}
+
+fn path_to_uri(path: &Path) -> String {
+    let mut uri = String::from("file://");
+    for b in path.to_string_lossy().bytes() {
+        match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'/' | b'-' | b'_' | b'.' | b'~' => {
+                uri.push(b as char)
+            }
+            b => uri.push_str(&format!("%{b:02X}")),
+        }
+    }
+    uri
+}
+
+/// Turns a position in `text` into an LSP position, which counts from 0, and
+/// counts characters in UTF-16 code units.
+fn lsp_position(text: &str, position: Position) -> Json {
+    let line = text
+        .lines()
+        .nth(position.line.saturating_sub(1))
+        .unwrap_or("");
+    let column = position.column.saturating_sub(1);
+    let units: usize = line.chars().take(column).map(char::len_utf16).sum();
+    Json::object([
+        ("line", Json::from(position.line.saturating_sub(1))),
+        (
+            "character",
+            Json::from(units + column.saturating_sub(line.chars().count())),
+        ),
+    ])
+}
+
+/// Turns an LSP position in `text` back into a position in the source.
+fn source_position(text: &str, line: usize, character: usize) -> Position {
+    let mut units = 0;
+    let mut column = 1;
+    for c in text.lines().nth(line).unwrap_or("").chars() {
+        if units >= character {
+            break;
+        }
+        units += c.len_utf16();
+        column += 1;
+    }
+    Position {
+        line: line + 1,
+        column: column + character.saturating_sub(units),
+    }
+}
+
+fn lsp_range(text: &str, position: Position, length: usize) -> Json {
+    Json::object([
+        ("start", lsp_position(text, position)),
+        (
+            "end",
+            lsp_position(
+                text,
+                Position {
+                    line: position.line,
+                    column: position.column + length,
+                },
+            ),
+        ),
+    ])
+}
+
+fn location(path: &Path, text: &str, position: Position, length: usize) -> Json {
+    Json::object([
+        ("uri", Json::from(path_to_uri(path))),
+        ("range", lsp_range(text, position, length)),
+    ])
+}
+
+/// Reads one message, or `None` at the end of input.
+fn read_message(input: &mut impl BufRead) -> io::Result<Option<String>> {
+    let mut length: Option<usize> = None;
+    loop {
+        let mut line = String::new();
+        if input.read_line(&mut line)? == 0 {
+            return Ok(None);
+        }
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = line.split_once(':')
+            && name.eq_ignore_ascii_case("content-length")
+        {
+            length = value.trim().parse().ok();
+        }
+    }
+    let mut body = vec![0; length.unwrap_or(0)];
+    input.read_exact(&mut body)?;
+    Ok(Some(String::from_utf8_lossy(&body).to_string()))
+}
+
+fn write_message(output: &mut impl Write, message: &Json) -> io::Result<()> {
+    let body = message.to_string();
+    write!(output, "Content-Length: {}\r\n\r\n{body}", body.len())?;
+    output.flush()
+}
+
+impl Server {
+    fn new() -> Self {
+        Server {
+            documents: HashMap::new(),
+            shut_down: false,
+            outbox: Vec::new(),
+        }
+    }
+
+    fn respond(&mut self, id: Json, result: Json) {
+        self.outbox.push(Json::object([
+            ("jsonrpc", Json::from("2.0")),
+            ("id", id),
+            ("result", result),
+        ]));
+    }
+
+    fn respond_error(&mut self, id: Json, code: i64, message: &str) {
+        self.outbox.push(Json::object([
+            ("jsonrpc", Json::from("2.0")),
+            ("id", id),
+            (
+                "error",
+                Json::object([
+                    ("code", Json::Number(code as f64)),
+                    ("message", Json::from(message)),
+                ]),
+            ),
+        ]));
+    }
+
+    fn notify(&mut self, method: &str, params: Json) {
+        self.outbox.push(Json::object([
+            ("jsonrpc", Json::from("2.0")),
+            ("method", Json::from(method)),
+            ("params", params),
+        ]));
+    }
+
+    /// Stores a new version of a document and publishes its diagnostics.
+    /// Documents are only checked, since running their immediate words on
+    /// every change could do anything.
+    fn update(&mut self, path: PathBuf, text: String) {
+        let diagnostics: Vec<Json> = match check(&text, Some(&path)) {
+            Ok(_) => vec![],
+            Err(e) => vec![Json::object([
+                (
+                    "range",
+                    lsp_range(
+                        &text,
+                        e.position().unwrap_or(Position { line: 1, column: 1 }),
+                        1,
+                    ),
+                ),
+                ("severity", Json::from(DIAGNOSTIC_ERROR)),
+                ("source", Json::from("forest")),
+                ("message", Json::from(e.to_string())),
+            ])],
+        };
+        self.notify(
+            "textDocument/publishDiagnostics",
+            Json::object([
+                ("uri", Json::from(path_to_uri(&path))),
+                ("diagnostics", Json::from(diagnostics)),
+            ]),
+        );
+        let analysis = analyze(&text).ok();
+        let document = self.documents.entry(path).or_insert(Document {
+            text: String::new(),
+            analysis: None,
+        });
+        document.text = text;
+        if analysis.is_some() {
+            document.analysis = analysis;
+        }
+    }
+
+    /// Analyzes a file that isn't open, or an open one's current text.
+    fn read(&self, path: &Path) -> Option<String> {
+        match self.documents.get(path) {
+            Some(document) => Some(document.text.clone()),
+            None => fs::read_to_string(path).ok(),
+        }
+    }
+
+    fn visible(&self, path: &Path) -> Vec<SourceFile> {
+        visible(path, |p| self.read(p))
+    }
+
+    /// Finds the word under the cursor of a request, with its document.
+    fn word_at(&self, params: &Json) -> Option<(PathBuf, String)> {
+        let path = uri_to_path(params.at(&["textDocument", "uri"])?.as_str()?);
+        let document = self.documents.get(&path)?;
+        let position = source_position(
+            &document.text,
+            params.at(&["position", "line"])?.as_usize()?,
+            params.at(&["position", "character"])?.as_usize()?,
+        );
+        let token = document.analysis.as_ref()?.token_at(position)?;
+        Some((path, token.raw.clone()))
+    }
+
+    fn definition(&self, params: &Json) -> Json {
+        let Some((path, word)) = self.word_at(params) else {
+            return Json::Null;
+        };
+        for file in self.visible(&path) {
+            if let (Source::File(p), Some(d)) = (&file.source, file.analysis.definition(&word)) {
+                return location(p, &file.text, d.position, d.name.chars().count());
+            }
+        }
+        Json::Null
+    }
+
+    fn references(&self, params: &Json) -> Json {
+        let Some((path, word)) = self.word_at(params) else {
+            return Json::Null;
+        };
+        let visible = self.visible(&path);
+        let Some(definition) = visible.iter().find_map(|f| f.analysis.definition(&word)) else {
+            return Json::Null;
+        };
+        let include_declaration = params
+            .at(&["context", "includeDeclaration"])
+            .and_then(Json::as_bool)
+            .unwrap_or(true);
+        let mut locations = Vec::new();
+        for file in &visible {
+            let Source::File(p) = &file.source else {
+                continue;
+            };
+            for token in file.analysis.references(definition) {
+                let is_declaration = file
+                    .analysis
+                    .definitions
+                    .iter()
+                    .any(|d| d.position == token.position);
+                if include_declaration || !is_declaration {
+                    locations.push(location(
+                        p,
+                        &file.text,
+                        token.position,
+                        token.raw.chars().count(),
+                    ));
+                }
+            }
+        }
+        Json::from(locations)
+    }
+
+    fn hover(&self, params: &Json) -> Json {
+        let Some((path, word)) = self.word_at(params) else {
+            return Json::Null;
+        };
+        let visible = self.visible(&path);
+        match visible.iter().find_map(|f| f.analysis.definition(&word)) {
+            Some(definition) => Json::object([(
+                "contents",
+                Json::object([
+                    ("kind", Json::from("markdown")),
+                    (
+                        "value",
+                        Json::from(format!("```forest\n{}\n```", definition.signature())),
+                    ),
+                ]),
+            )]),
+            None => Json::Null,
+        }
+    }
+
+    fn completion(&self, params: &Json) -> Json {
+        let mut items: Vec<Json> = BUILTIN_WORDS
+            .iter()
+            .map(|w| {
+                Json::object([
+                    ("label", Json::from(*w)),
+                    ("kind", Json::from(COMPLETION_KEYWORD)),
+                ])
+            })
+            .collect();
+        let Some(path) = params
+            .at(&["textDocument", "uri"])
+            .and_then(Json::as_str)
+            .map(uri_to_path)
+        else {
+            return Json::from(items);
+        };
+        let mut names: HashSet<String> = HashSet::new();
+        for file in self.visible(&path) {
+            for definition in &file.analysis.definitions {
+                // The variables of bundled libraries are their own business.
+                let internal = matches!(file.source, Source::Bundled)
+                    && matches!(
+                        definition.kind,
+                        DefinitionKind::Variable | DefinitionKind::Constant
+                    );
+                if !internal && names.insert(definition.name.clone()) {
+                    items.push(completion_item(definition));
+                }
+            }
+        }
+        Json::from(items)
+    }
+
+    /// Handles a message, and tells whether to keep going.
+    fn handle(&mut self, message: &Json) -> bool {
+        let method = message.get("method").and_then(Json::as_str).unwrap_or("");
+        let params = message.get("params").cloned().unwrap_or(Json::Null);
+        let Some(id) = message.get("id").cloned() else {
+            // Notifications get no response.
+            match method {
+                "textDocument/didOpen" => {
+                    if let (Some(uri), Some(text)) = (
+                        params.at(&["textDocument", "uri"]).and_then(Json::as_str),
+                        params.at(&["textDocument", "text"]).and_then(Json::as_str),
+                    ) {
+                        self.update(uri_to_path(uri), text.to_string());
+                    }
+                }
+                "textDocument/didChange" => {
+                    // Documents are synced in full, so the last change has all of the text.
+                    let text = match params.get("contentChanges") {
+                        Some(Json::Array(changes)) => changes
+                            .last()
+                            .and_then(|c| c.get("text"))
+                            .and_then(Json::as_str),
+                        _ => None,
+                    };
+                    if let (Some(uri), Some(text)) = (
+                        params.at(&["textDocument", "uri"]).and_then(Json::as_str),
+                        text,
+                    ) {
+                        self.update(uri_to_path(uri), text.to_string());
+                    }
+                }
+                "textDocument/didClose" => {
+                    if let Some(uri) = params.at(&["textDocument", "uri"]).and_then(Json::as_str) {
+                        self.documents.remove(&uri_to_path(uri));
+                    }
+                }
+                "exit" => return false,
+                _ => {}
+            }
+            return true;
+        };
+        let result = match method {
+            "initialize" => Json::object([
+                (
+                    "capabilities",
+                    Json::object([
+                        ("textDocumentSync", Json::from(1)),
+                        ("definitionProvider", Json::from(true)),
+                        ("referencesProvider", Json::from(true)),
+                        ("hoverProvider", Json::from(true)),
+                        ("completionProvider", Json::object([])),
+                    ]),
+                ),
+                (
+                    "serverInfo",
+                    Json::object([
+                        ("name", Json::from("forest")),
+                        ("version", Json::from(env!("CARGO_PKG_VERSION"))),
+                    ]),
+                ),
+            ]),
+            "shutdown" => {
+                self.shut_down = true;
+                Json::Null
+            }
+            "textDocument/definition" => self.definition(&params),
+            "textDocument/references" => self.references(&params),
+            "textDocument/hover" => self.hover(&params),
+            "textDocument/completion" => self.completion(&params),
+            _ => {
+                self.respond_error(id, METHOD_NOT_FOUND, &format!("Unknown method {method}"));
+                return true;
+            }
+        };
+        self.respond(id, result);
+        true
+    }
+}
+
+fn completion_item(definition: &Definition) -> Json {
+    let kind = match definition.kind {
+        DefinitionKind::Word | DefinitionKind::ConstantWord => COMPLETION_FUNCTION,
+        DefinitionKind::Variable | DefinitionKind::Constant => COMPLETION_VARIABLE,
+    };
+    let mut item = vec![
+        ("label".to_string(), Json::from(definition.name.clone())),
+        ("kind".to_string(), Json::from(kind)),
+    ];
+    if let Some(effect) = &definition.effect {
+        item.push(("detail".to_string(), Json::from(effect.clone())));
+    }
+    Json::Object(item)
+}
+
+/// Speaks the Language Server Protocol over stdin and stdout until the
+/// client asks the server to exit. Fails if the client exits without
+/// shutting the server down first, as the protocol says.
+pub fn run_lsp() -> Result<(), ()> {
+    let mut server = Server::new();
+    let stdin = io::stdin();
+    let mut input = stdin.lock();
+    let mut output = io::stdout().lock();
+    loop {
+        let body = match read_message(&mut input) {
+            Ok(Some(body)) => body,
+            Ok(None) | Err(_) => return Err(()),
+        };
+        let keep_going = match Json::parse(&body) {
+            Ok(message) => server.handle(&message),
+            Err(e) => {
+                server.respond_error(Json::Null, PARSE_ERROR, &e);
+                true
+            }
+        };
+        for message in server.outbox.drain(..) {
+            _ = write_message(&mut output, &message);
+        }
+        if !keep_going {
+            return if server.shut_down { Ok(()) } else { Err(()) };
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const URI: &str = "file:///tmp/lsp%20test.frt";
+
+    /// Handles a message, and gives back what the server sent.
+    fn exchange(server: &mut Server, message: &str) -> Vec<String> {
+        server.handle(&Json::parse(message).unwrap());
+        server.outbox.drain(..).map(|m| m.to_string()).collect()
+    }
+
+    fn open(server: &mut Server, text: &str) -> Vec<String> {
+        let params = Json::object([(
+            "textDocument",
+            Json::object([("uri", Json::from(URI)), ("text", Json::from(text))]),
+        )]);
+        exchange(
+            server,
+            &format!(r#"{{"method":"textDocument/didOpen","params":{params}}}"#),
+        )
+    }
+
+    fn request(server: &mut Server, method: &str, line: usize, character: usize) -> String {
+        let message = format!(
+            r#"{{"id":1,"method":"{method}","params":{{"textDocument":{{"uri":"{URI}"}},"position":{{"line":{line},"character":{character}}}}}}}"#
+        );
+        exchange(server, &message).join("\n")
+    }
+
+    #[test]
+    fn messages_are_framed_by_their_length() {
+        let mut output = Vec::new();
+        write_message(&mut output, &Json::object([("id", Json::from(1))])).unwrap();
+        assert_eq!(output, b"Content-Length: 8\r\n\r\n{\"id\":1}");
+        let mut input = &output[..];
+        assert_eq!(
+            read_message(&mut input).unwrap().as_deref(),
+            Some("{\"id\":1}")
+        );
+        assert_eq!(read_message(&mut input).unwrap(), None);
+    }
+
+    #[test]
+    fn uris_are_decoded_into_paths() {
+        let path = uri_to_path(URI);
+        assert_eq!(path, PathBuf::from("/tmp/lsp test.frt"));
+        assert_eq!(path_to_uri(&path), URI);
+    }
+
+    #[test]
+    fn unknown_methods_are_errors() {
+        let mut server = Server::new();
+        assert_eq!(
+            exchange(&mut server, r#"{"id":7,"method":"nonsense"}"#),
+            [
+                r#"{"jsonrpc":"2.0","id":7,"error":{"code":-32601,"message":"Unknown method nonsense"}}"#
+            ]
+        );
+        assert!(exchange(&mut server, r#"{"method":"nonsense"}"#).is_empty());
+    }
+
+    #[test]
+    fn shutting_down_then_exiting() {
+        let mut server = Server::new();
+        let initialized = exchange(&mut server, r#"{"id":1,"method":"initialize"}"#);
+        assert!(initialized[0].contains(r#""definitionProvider":true"#));
+        assert_eq!(
+            exchange(&mut server, r#"{"id":2,"method":"shutdown"}"#),
+            [r#"{"jsonrpc":"2.0","id":2,"result":null}"#]
+        );
+        assert!(!server.handle(&Json::parse(r#"{"method":"exit"}"#).unwrap()));
+        assert!(server.shut_down);
+    }
+
+    #[test]
+    fn diagnostics_point_at_the_error_in_utf16() {
+        let mut server = Server::new();
+        // The emoji takes two UTF-16 code units.
+        assert_eq!(
+            open(&mut server, "\"😀\" 10 0 do"),
+            [format!(
+                r#"{{"jsonrpc":"2.0","method":"textDocument/publishDiagnostics","params":{{"uri":"{URI}","diagnostics":[{{"range":{{"start":{{"line":0,"character":10}},"end":{{"line":0,"character":11}}}},"severity":1,"source":"forest","message":"1:10: `do` is never closed with `loop`!"}}]}}}}"#
+            )]
+        );
+        assert!(open(&mut server, "1 0 do loop")[0].contains(r#""diagnostics":[]"#));
+    }
+
+    #[test]
+    fn diagnostics_run_no_immediate_words() {
+        let mut server = Server::new();
+        let published = open(&mut server, ": boom \"boom\" throw ; immediate boom");
+        assert!(published[0].contains(r#""diagnostics":[]"#));
+    }
+
+    #[test]
+    fn definitions_and_references() {
+        let mut server = Server::new();
+        open(&mut server, ": sq dup * ;\n\"😀\" drop 3 sq .");
+        // The `q` of `sq` is the 13th UTF-16 code unit of the second line.
+        assert_eq!(
+            request(&mut server, "textDocument/definition", 1, 13),
+            format!(
+                r#"{{"jsonrpc":"2.0","id":1,"result":{{"uri":"{URI}","range":{{"start":{{"line":0,"character":2}},"end":{{"line":0,"character":4}}}}}}}}"#
+            )
+        );
+        let references = request(&mut server, "textDocument/references", 0, 2);
+        assert!(
+            references
+                .contains(r#"{"start":{"line":1,"character":12},"end":{"line":1,"character":14}}"#)
+        );
+        assert_eq!(
+            request(&mut server, "textDocument/definition", 1, 11),
+            r#"{"jsonrpc":"2.0","id":1,"result":null}"#
+        );
+    }
+
+    #[test]
+    fn hover_shows_the_definition() {
+        let mut server = Server::new();
+        open(&mut server, ": sq ( n -- n ) dup * ;\n3 sq .");
+        assert_eq!(
+            request(&mut server, "textDocument/hover", 1, 2),
+            r#"{"jsonrpc":"2.0","id":1,"result":{"contents":{"kind":"markdown","value":"```forest\n: sq ( n -- n ) dup * ;\n```"}}}"#
+        );
+    }
+
+    #[test]
+    fn completion_offers_builtins_and_definitions() {
+        let mut server = Server::new();
+        open(&mut server, ": sq ( n -- n ) dup * ;\n3 -> x");
+        let completions = request(&mut server, "textDocument/completion", 1, 0);
+        assert!(completions.contains(r#"{"label":"dup","kind":14}"#));
+        assert!(completions.contains(r#"{"label":"sq","kind":3,"detail":"( n -- n )"}"#));
+        assert!(completions.contains(r#"{"label":"x","kind":6}"#));
+    }
+}
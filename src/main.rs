@@ -1,72 +1,168 @@
 mod forest_runtime;
 use forest_runtime::*;
 mod compile;
-use compile::compile;
+use compile::compile_mapped;
+mod analysis;
+mod bytecode;
+mod effects;
+mod formatter;
+mod golden;
+mod json;
+mod lexer;
+mod lint;
+mod lsp;
+mod test_runner;
+use bytecode::{Bytecode, run_build};
+use effects::run_check;
+use formatter::run_fmt;
+use golden::run_golden;
+use lint::run_lint;
+use lsp::run_lsp;
 use std::env;
 use std::fs::File;
 use std::io::Read;
+use std::path::Path;
+use std::process::ExitCode;
+use test_runner::run_tests;
 
-fn main() -> Result<(), ()> {
+// Exit statuses for failures, so that scripts can tell them apart. Programs
+// that `exit` choose their own status.
+const EXIT_RUNTIME_ERROR: u8 = 1;
+const EXIT_COMPILE_ERROR: u8 = 2;
+const EXIT_USAGE_ERROR: u8 = 64;
+
+fn exit_code(result: Result<(), ()>) -> ExitCode {
+    match result {
+        Ok(_) => ExitCode::SUCCESS,
+        Err(_) => ExitCode::FAILURE,
+    }
+}
+
+fn main() -> ExitCode {
     let mut args = env::args();
     args.next().expect("Something went horribly wrong - there should be at least one argument being the name of the program");
     match args.next() {
         Some(a) => match a.as_str() {
             "run" => {}
+            "build" => return exit_code(run_build(&args.collect::<Vec<String>>())),
+            "test" => return exit_code(run_tests(&args.collect::<Vec<String>>())),
+            "golden" => return exit_code(run_golden(&args.collect::<Vec<String>>())),
+            "lsp" => return exit_code(run_lsp()),
+            "fmt" => return exit_code(run_fmt(&args.collect::<Vec<String>>())),
+            "check" => return exit_code(run_check(&args.collect::<Vec<String>>())),
+            "lint" => return exit_code(run_lint(&args.collect::<Vec<String>>())),
             s => {
                 eprintln!("Unknown option {s} - try running `forest`");
-                return Err(());
+                return ExitCode::from(EXIT_USAGE_ERROR);
             }
         },
         None => {
             println!(
                 "\
                 Welcome to the forest compiler!\n\n\
-                info: Usage: forest [command] {{options}}\n\n\
+                info: Usage: forest [command] {{options}} [-- arguments for the program]\n\n\
                 Commands:\n\
-                  run  | run from source\n\n\
+                  run          | run from source, or from bytecode built with `build`\n\
+                  build        | compile a file to bytecode, to run it without compiling\n\
+                  test         | run the test-* words in the given files or directories\n\
+                  golden       | compare the output of programs with their .out files\n\
+                  lsp          | run a language server over stdin and stdout\n\
+                  fmt          | format the given files or directories in place\n\
+                  check        | check the stack effects of the given files or directories\n\
+                  lint         | look for common mistakes in the given files or directories\n\n\
                 Options:\n\
-                  dump | dump stack during runtime\n\
+                  dump         | dump stack during runtime\n\
+                  --no-prelude | do not load the standard library\n\
+                  --update     | (golden) rewrite the .out files\n\
+                  --check      | (fmt) only report files that aren't formatted\n\
+                  --types      | (check) also check the types of values\n\
+                  --allow      | (lint) do not report the lint with the given code\n\
+                  -o           | (build) the file to write, by default the source with .frc\n\
                 "
             );
-            return Ok(());
+            return ExitCode::SUCCESS;
         }
     }
     if let Some(filepath) = args.next() {
         if let Ok(mut file) = File::open(&filepath) {
-            let mut program = String::new();
-            match file.read_to_string(&mut program) {
-                Ok(_) => {}
-                Err(e) => {
-                    eprintln!("Could not read file, reason: {e}");
-                    return Err(());
-                }
+            let bytecode = match load_program(&mut file, &filepath) {
+                Ok(b) => b,
+                Err(code) => return code,
             };
-            let instrs = compile(&program)
-                .map_err(|err| {
-                    eprintln!("Error in compilation: {err}");
-                    return 1;
-                })
-                .unwrap();
-            let mut runtime = ForestRuntime::new(&instrs);
-            let dump = match args.next() {
-                Some(o) => {
-                    if o == "dump" {
-                        true
-                    } else {
+            let mut runtime = ForestRuntime::new(&bytecode.program);
+            runtime.set_source_path(Path::new(&bytecode.source));
+            runtime.set_source_map(&bytecode.source, &bytecode.positions);
+            let mut dump = false;
+            let mut prelude = true;
+            while let Some(o) = args.next() {
+                match o.as_str() {
+                    "dump" => dump = true,
+                    "--no-prelude" => prelude = false,
+                    "--" => {
+                        runtime.set_args(args.by_ref().collect());
+                    }
+                    _ => {
                         eprintln!("Unknown option {o}");
-                        return Err(());
+                        return ExitCode::from(EXIT_USAGE_ERROR);
                     }
                 }
-                _ => false,
-            };
-            execute_runtime!(runtime, dump);
+            }
+            if prelude {
+                runtime.load_prelude();
+            }
+            match execute_runtime!(runtime, dump) {
+                // Statuses are truncated to a byte, like the operating system does.
+                Ok(code) => ExitCode::from(code as u8),
+                Err(ForestError::IncludeCompileError(_) | ForestError::EvalCompileError(_)) => {
+                    ExitCode::from(EXIT_COMPILE_ERROR)
+                }
+                Err(_) => ExitCode::from(EXIT_RUNTIME_ERROR),
+            }
         } else {
             eprintln!("Cannot find {filepath} in current directory");
-            return Err(());
+            ExitCode::from(EXIT_USAGE_ERROR)
         }
     } else {
         eprintln!("Please provide a file name!");
-        return Err(());
+        ExitCode::from(EXIT_USAGE_ERROR)
+    }
+}
+
+/// Reads a program and compiles it, or decodes it if it was built to bytecode.
+fn load_program(file: &mut File, filepath: &str) -> Result<Bytecode, ExitCode> {
+    let mut bytes = Vec::new();
+    if let Err(e) = file.read_to_end(&mut bytes) {
+        eprintln!("Could not read file, reason: {e}");
+        return Err(ExitCode::from(EXIT_USAGE_ERROR));
+    }
+    if Path::new(filepath)
+        .extension()
+        .is_some_and(|e| e == bytecode::EXTENSION)
+    {
+        return match bytecode::decode(&bytes) {
+            Ok(b) => Ok(b),
+            Err(e) => {
+                eprintln!("Could not load {filepath}: {e}");
+                Err(ExitCode::from(EXIT_COMPILE_ERROR))
+            }
+        };
+    }
+    let program = match String::from_utf8(bytes) {
+        Ok(p) => p,
+        Err(e) => {
+            eprintln!("Could not read file, reason: {e}");
+            return Err(ExitCode::from(EXIT_USAGE_ERROR));
+        }
+    };
+    match compile_mapped(&program, Some(Path::new(filepath))) {
+        Ok((program, positions)) => Ok(Bytecode {
+            source: filepath.to_string(),
+            program,
+            positions,
+        }),
+        Err(err) => {
+            eprintln!("Error in compilation: {err}");
+            Err(ExitCode::from(EXIT_COMPILE_ERROR))
+        }
     }
-    Ok(())
 }
@@ -0,0 +1,121 @@
+use crate::compile::compile;
+use crate::forest_runtime::{ForestInstruction, ForestRuntime, ForestStatus};
+use crate::lexer::lex;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Words whose names start with this are run by `forest test`.
+pub const TEST_PREFIX: &str = "test-";
+
+pub fn collect_files(path: &Path, files: &mut Vec<PathBuf>) {
+    if path.is_dir() {
+        let mut entries: Vec<PathBuf> = match fs::read_dir(path) {
+            Ok(rd) => rd.filter_map(|e| e.ok()).map(|e| e.path()).collect(),
+            Err(_) => return,
+        };
+        entries.sort();
+        entries.iter().for_each(|p| collect_files(p, files));
+    } else if path.extension().is_some_and(|e| e == "frt") {
+        files.push(path.to_path_buf());
+    }
+}
+
+/// Finds the line a word is defined on.
+fn definition_line(source: &str, name: &str) -> Option<usize> {
+    let tokens = lex(source).ok()?;
+    tokens.windows(2).find_map(|w| {
+        (matches!(w[0].raw.as_str(), ":" | "::") && w[1].raw == name).then_some(w[1].position.line)
+    })
+}
+
+/// Runs a runtime until it stops, as if it was given no input.
+fn run_to_end(runtime: &mut ForestRuntime) -> ForestStatus {
+    loop {
+        match runtime.run_until(|_| false) {
+            ForestStatus::WaitingForInput => runtime.provide_input(None),
+            status => return status,
+        }
+    }
+}
+
+/// Runs every test word found in `paths` in a fresh runtime each, printing a
+/// line per test and a summary. Fails if any test fails.
+pub fn run_tests(paths: &[String]) -> Result<(), ()> {
+    let mut files: Vec<PathBuf> = Vec::new();
+    if paths.is_empty() {
+        collect_files(Path::new("."), &mut files);
+    } else {
+        paths
+            .iter()
+            .for_each(|p| collect_files(Path::new(p), &mut files));
+    }
+    let mut passed = 0;
+    let mut failed = 0;
+    for file in files {
+        let source = match fs::read_to_string(&file) {
+            Ok(s) => s,
+            Err(e) => {
+                eprintln!("FAIL {}: could not read file, reason: {e}", file.display());
+                failed += 1;
+                continue;
+            }
+        };
+        let program = match compile(&source, Some(&file)) {
+            Ok(p) => p,
+            Err(e) => {
+                eprintln!("FAIL {}: error in compilation: {e}", file.display());
+                failed += 1;
+                continue;
+            }
+        };
+        let tests: Vec<String> = program
+            .iter()
+            .filter_map(|inst| match inst {
+                ForestInstruction::MakeWord(n) | ForestInstruction::MakeWordVar(n)
+                    if n.starts_with(TEST_PREFIX) =>
+                {
+                    Some(n.clone())
+                }
+                _ => None,
+            })
+            .collect();
+        for test in tests {
+            let location = match definition_line(&source, &test) {
+                Some(line) => format!("{}:{line}", file.display()),
+                None => file.display().to_string(),
+            };
+            let mut runtime = ForestRuntime::new(&program);
+            runtime.set_source_path(&file);
+            runtime.load_prelude();
+            // The rest of the file runs first, to define what the test needs.
+            let status = match run_to_end(&mut runtime) {
+                ForestStatus::Halted(_) if runtime.next_instruction().is_some() => {
+                    println!("FAIL {location} {test}: the file exited before the test ran");
+                    failed += 1;
+                    continue;
+                }
+                ForestStatus::Halted(_) => {
+                    runtime.push_instrs(&[ForestInstruction::InvokeWord(test.clone())]);
+                    run_to_end(&mut runtime)
+                }
+                status => status,
+            };
+            match status {
+                ForestStatus::Errored(e) => {
+                    println!("FAIL {location} {test}: {e}");
+                    failed += 1;
+                }
+                ForestStatus::Halted(code) if code != 0 => {
+                    println!("FAIL {location} {test}: exited with status {code}");
+                    failed += 1;
+                }
+                _ => {
+                    println!("PASS {location} {test}");
+                    passed += 1;
+                }
+            }
+        }
+    }
+    println!("\n{passed} passed, {failed} failed");
+    if failed == 0 { Ok(()) } else { Err(()) }
+}